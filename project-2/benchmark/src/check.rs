@@ -0,0 +1,205 @@
+use std::{collections::HashMap, str::FromStr, time::Duration};
+
+use anyhow::Context;
+use hyper::header::HeaderName;
+use serde_json::Value;
+
+use crate::{client::Response, extract::json_text, JsonPath, StatusSet};
+
+/// A condition every response of an endpoint or step is checked against. Checks
+/// are only run on responses, a request that got none is counted as an error instead.
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// Name the pass rate is reported under, checks with the same name are counted together
+    pub name: String,
+    pub condition: Condition,
+}
+
+/// What a response must be like to pass a check.
+#[derive(Debug, Clone)]
+pub enum Condition {
+    /// The status is one of the set
+    Status(StatusSet),
+    /// The header is present, and has this value when one is given
+    Header(HeaderName, Option<String>),
+    /// The body contains the text
+    BodyContains(String),
+    /// The value at the path of the JSON body, strings compared without their quotes
+    JsonEquals(JsonPath, String),
+    /// The whole response took at most this long
+    MaxLatency(Duration),
+}
+
+/// How many responses passed and failed a check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CheckCount {
+    pub passed: u64,
+    pub failed: u64,
+}
+
+impl CheckCount {
+    pub fn add(&mut self, other: &CheckCount) {
+        self.passed += other.passed;
+        self.failed += other.failed;
+    }
+}
+
+impl Check {
+    pub fn new(name: impl Into<String>, condition: Condition) -> Self {
+        Self {
+            name: name.into(),
+            condition,
+        }
+    }
+}
+
+impl Condition {
+    pub(crate) fn needs_body(&self) -> bool {
+        matches!(
+            self,
+            Condition::BodyContains(_) | Condition::JsonEquals(_, _)
+        )
+    }
+
+    // `json` is the body parsed once for every check of the request
+    pub(crate) fn passes(
+        &self,
+        response: &Response,
+        latency: Duration,
+        json: Option<&Value>,
+    ) -> bool {
+        match self {
+            Condition::Status(statuses) => statuses.contains(response.status),
+            Condition::Header(name, value) => match (response.headers.get(name), value) {
+                (Some(found), Some(value)) => found == value.as_str(),
+                (found, None) => found.is_some(),
+                (None, _) => false,
+            },
+            Condition::BodyContains(text) => {
+                let body = String::from_utf8_lossy(&response.body);
+                body.contains(text.as_str())
+            }
+            Condition::JsonEquals(path, expected) => json
+                .and_then(|json| path.find(json))
+                .is_some_and(|value| json_text(value) == *expected),
+            Condition::MaxLatency(max) => latency <= *max,
+        }
+    }
+}
+
+/// Parses `status:200,201,2xx`, `header:Name`, `header:Name=value`, `body:text`,
+/// `json:$.path=value` or `latency:250ms`.
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, rest) = s.split_once(':').with_context(|| {
+            format!(
+                "Check {:?} is not in the form 'status:2xx', 'header:Name[=value]', \
+                 'body:text', 'json:$.path=value' or 'latency:250ms'",
+                s
+            )
+        })?;
+        Ok(match kind.trim() {
+            "status" => Condition::Status(rest.parse()?),
+            "header" => {
+                let (name, value) = match rest.split_once('=') {
+                    Some((name, value)) => (name, Some(value.trim().to_string())),
+                    None => (rest, None),
+                };
+                let name = HeaderName::from_bytes(name.trim().as_bytes())
+                    .with_context(|| format!("Invalid header name {:?}", name))?;
+                Condition::Header(name, value)
+            }
+            "body" => Condition::BodyContains(rest.to_string()),
+            "json" => {
+                let (path, value) = rest
+                    .split_once('=')
+                    .with_context(|| format!("Check {:?} has no '=value'", s))?;
+                Condition::JsonEquals(path.parse()?, value.trim().to_string())
+            }
+            "latency" => Condition::MaxLatency(
+                humantime::parse_duration(rest.trim())
+                    .with_context(|| format!("Invalid latency {:?}", rest))?,
+            ),
+            _ => anyhow::bail!(
+                "Unknown check {:?}, expected status, header, body, json or latency",
+                kind
+            ),
+        })
+    }
+}
+
+// Counts the checks of a response by name, returns whether they all passed
+pub(crate) fn run_checks(
+    checks: &[Check],
+    response: &Response,
+    latency: Duration,
+    counts: &mut HashMap<String, CheckCount>,
+) -> bool {
+    let json = checks
+        .iter()
+        .any(|c| matches!(c.condition, Condition::JsonEquals(_, _)))
+        .then(|| serde_json::from_slice::<Value>(&response.body).ok())
+        .flatten();
+    let mut all_passed = true;
+    for check in checks {
+        let passed = check.condition.passes(response, latency, json.as_ref());
+        let count = counts.entry(check.name.clone()).or_default();
+        if passed {
+            count.passed += 1;
+        } else {
+            count.failed += 1;
+        }
+        all_passed &= passed;
+    }
+    all_passed
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::HeaderMap;
+
+    use super::*;
+
+    #[test]
+    fn checks_responses() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", "application/json".parse().unwrap());
+        let response = Response {
+            status: 201,
+            version: String::from("HTTP/1.1"),
+            time_to_first_byte: Duration::ZERO,
+            body_bytes: 0,
+            headers,
+            body: br#"{"user": {"id": 7, "name": "Ada"}}"#.to_vec(),
+        };
+        let json = serde_json::from_slice(&response.body).unwrap();
+        let passes = |condition: &str| {
+            condition.parse::<Condition>().unwrap().passes(
+                &response,
+                Duration::from_millis(300),
+                Some(&json),
+            )
+        };
+
+        assert!(passes("status:200,2xx"));
+        assert!(!passes("status:200,304"));
+        assert!(passes("header:Content-Type"));
+        assert!(passes("header:content-type=application/json"));
+        assert!(!passes("header:content-type=text/html"));
+        assert!(!passes("header:etag"));
+        assert!(passes("body:\"name\": \"Ada\""));
+        assert!(passes("json:$.user.name=Ada"));
+        assert!(passes("json:user.id=7"));
+        assert!(!passes("json:$.user.missing=7"));
+        assert!(passes("latency:500ms"));
+        assert!(!passes("latency:250ms"));
+
+        assert!("status:6xx".parse::<Condition>().is_err());
+        assert!("status:abc".parse::<Condition>().is_err());
+        assert!("json:$.user".parse::<Condition>().is_err());
+        assert!("latency:fast".parse::<Condition>().is_err());
+        assert!("cookie:session".parse::<Condition>().is_err());
+    }
+}
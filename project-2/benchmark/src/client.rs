@@ -0,0 +1,255 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Ok;
+use async_trait::async_trait;
+use hyper::{body::HttpBody, client::HttpConnector, Body, Client, HeaderMap, Request};
+use tokio::time::timeout;
+
+use crate::{
+    tls::{self, TlsConnector},
+    BenchmarkSettings,
+};
+
+#[async_trait]
+pub(crate) trait Requester {
+    /// Only keeps the response body when `keep_body` is set, otherwise it is read and dropped
+    async fn send(&self, request: Request<Body>, keep_body: bool) -> anyhow::Result<Response>;
+
+    /// Durations of the TLS handshakes done since the last call
+    fn take_tls_handshakes(&self) -> Vec<Duration> {
+        vec![]
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) version: String,
+    pub(crate) time_to_first_byte: Duration,
+    pub(crate) body_bytes: u64,
+    pub(crate) headers: HeaderMap,
+    // Empty unless asked for
+    pub(crate) body: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub(crate) struct ClientSettings {
+    pub(crate) request_timeout: Option<Duration>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) http2: bool,
+    pub(crate) tls: Arc<rustls::ClientConfig>,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            request_timeout: None,
+            connect_timeout: None,
+            http2: false,
+            tls: Arc::new(
+                tls::client_config(&Default::default()).expect("Default TLS settings are valid"),
+            ),
+        }
+    }
+}
+
+impl ClientSettings {
+    // Fails when the certificates the TLS settings point to cannot be loaded
+    pub(crate) fn from(value: &BenchmarkSettings) -> anyhow::Result<Self> {
+        let mut tls = tls::client_config(&value.tls)?;
+        tls.alpn_protocols = if value.http2 {
+            vec![b"h2".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        Ok(Self {
+            request_timeout: value.request_timeout,
+            connect_timeout: value.connect_timeout,
+            http2: value.http2,
+            tls: Arc::new(tls),
+        })
+    }
+}
+
+pub(crate) struct HttpClient {
+    client: Client<TlsConnector>,
+    request_timeout: Option<Duration>,
+    tls_handshakes: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(settings: &ClientSettings) -> Self {
+        let mut http = HttpConnector::new();
+        http.set_connect_timeout(settings.connect_timeout);
+        http.enforce_http(false);
+        let tls_handshakes = Arc::new(Mutex::new(vec![]));
+        let connector = TlsConnector::new(
+            http,
+            settings.tls.clone(),
+            settings.http2,
+            tls_handshakes.clone(),
+        );
+        // HTTP/2 only also makes concurrent requests wait for the first connection
+        // instead of each opening their own
+        HttpClient {
+            client: Client::builder()
+                .http2_only(settings.http2)
+                .build(connector),
+            request_timeout: settings.request_timeout,
+            tls_handshakes,
+        }
+    }
+}
+
+#[async_trait]
+impl Requester for HttpClient {
+    async fn send(&self, request: Request<Body>, keep_body: bool) -> anyhow::Result<Response> {
+        // The body is streamed to the end so transfer time is part of the latency,
+        // usually only its size is kept
+        let exchange = async {
+            let start = Instant::now();
+            let response = self.client.request(request).await?;
+            let time_to_first_byte = start.elapsed();
+            let status = response.status().as_u16();
+            let version = format!("{:?}", response.version());
+            let (parts, mut body) = response.into_parts();
+            let mut body_bytes = 0;
+            let mut kept = vec![];
+            while let Some(chunk) = body.data().await {
+                let chunk = chunk?;
+                body_bytes += chunk.len() as u64;
+                if keep_body {
+                    kept.extend_from_slice(&chunk);
+                }
+            }
+            Ok(Response {
+                status,
+                version,
+                time_to_first_byte,
+                body_bytes,
+                headers: parts.headers,
+                body: kept,
+            })
+        };
+        match self.request_timeout {
+            Some(request_timeout) => timeout(request_timeout, exchange).await?,
+            None => exchange.await,
+        }
+    }
+
+    fn take_tls_handshakes(&self) -> Vec<Duration> {
+        std::mem::take(&mut *self.tls_handshakes.lock().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{build_uri, RequestError, RequestTemplate, Variables};
+
+    fn get(uri: hyper::Uri) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn http_client_classifies_connect_refused() {
+        // Bind then drop a listener to get a local port nobody listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let uri = build_uri(&format!("http://{}/", listener.local_addr().unwrap()));
+        drop(listener);
+
+        let err = HttpClient::new(&ClientSettings::default())
+            .send(get(uri), false)
+            .await
+            .expect_err("Nothing listens");
+        assert_eq!(RequestError::classify(&err), RequestError::ConnectRefused);
+    }
+
+    #[tokio::test]
+    async fn http_client_times_out() {
+        // A listener that accepts connections but never answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = build_uri(&format!("http://{}/", listener.local_addr().unwrap()));
+        let client = HttpClient::new(&ClientSettings {
+            request_timeout: Some(Duration::from_millis(50)),
+            ..ClientSettings::default()
+        });
+
+        let err = client.send(get(uri), false).await.expect_err("No response");
+        assert_eq!(RequestError::classify(&err), RequestError::Timeout);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn http_client_sends_request_template() {
+        use hyper::{
+            service::{make_service_fn, service_fn},
+            Body, Method, Request, Response, Server,
+        };
+
+        // Answers 201 only to the exact request the template describes
+        let make_service = make_service_fn(|_| async {
+            Result::<_, hyper::Error>::Ok(service_fn(|req: Request<Body>| async move {
+                let matches = req.method() == Method::POST
+                    && req.headers().get("x-test").map(|v| v == "yes") == Some(true);
+                let body = hyper::body::to_bytes(req.into_body()).await?;
+                let status = if matches && body == "hello" { 201 } else { 400 };
+                Result::<_, hyper::Error>::Ok(
+                    Response::builder()
+                        .status(status)
+                        .body(Body::from("created"))
+                        .unwrap(),
+                )
+            }))
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(make_service);
+        let uri = build_uri(&format!("http://{}/", server.local_addr()));
+        tokio::spawn(server);
+
+        let template = RequestTemplate::get(&uri.to_string())
+            .unwrap()
+            .with_method("POST")
+            .unwrap()
+            .with_header("X-Test: yes")
+            .unwrap()
+            .with_body("hello")
+            .unwrap();
+        let response = HttpClient::new(&ClientSettings::default())
+            .send(template.build(&Variables::new()).unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body_bytes, 7);
+        assert_eq!(response.body, b"created");
+    }
+
+    #[tokio::test]
+    async fn http_client_speaks_h2c() {
+        use hyper::{
+            service::{make_service_fn, service_fn},
+            Body, Server,
+        };
+
+        let make_service = make_service_fn(|_| async {
+            Result::<_, hyper::Error>::Ok(service_fn(|_| async {
+                Result::<_, hyper::Error>::Ok(hyper::Response::new(Body::empty()))
+            }))
+        });
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap())
+            .http2_only(true)
+            .serve(make_service);
+        let uri = build_uri(&format!("http://{}/", server.local_addr()));
+        tokio::spawn(server);
+
+        let client = HttpClient::new(&ClientSettings {
+            http2: true,
+            ..ClientSettings::default()
+        });
+        let response = client.send(get(uri), false).await.unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.version, "HTTP/2.0");
+    }
+}
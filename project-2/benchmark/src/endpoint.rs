@@ -0,0 +1,109 @@
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+
+use crate::{Check, RequestTemplate};
+
+/// A request template with its share of the traffic.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    /// Name the statistics of the endpoint are reported under
+    pub name: String,
+    /// Share of the requests relative to the other endpoints, an endpoint
+    /// with weight 2 gets twice as many requests as one with weight 1
+    pub weight: u32,
+    pub request: RequestTemplate,
+    /// Run on every response of the endpoint
+    pub checks: Vec<Check>,
+}
+
+impl Endpoint {
+    pub fn new(name: impl Into<String>, request: RequestTemplate) -> Self {
+        Self {
+            name: name.into(),
+            weight: 1,
+            request,
+            checks: vec![],
+        }
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_check(mut self, check: Check) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub(crate) fn needs_body(&self) -> bool {
+        self.checks.iter().any(|c| c.condition.needs_body())
+    }
+}
+
+// Picks the endpoint of every request at random in proportion to the weights
+pub(crate) struct Mix {
+    endpoints: Vec<Endpoint>,
+    // Not needed for a single endpoint
+    weights: Option<WeightedIndex<u32>>,
+}
+
+impl Mix {
+    pub(crate) fn new(endpoints: Vec<Endpoint>) -> anyhow::Result<Self> {
+        anyhow::ensure!(!endpoints.is_empty(), "No endpoint to send requests to");
+        let weights = match endpoints.len() {
+            1 => None,
+            _ => Some(
+                WeightedIndex::new(endpoints.iter().map(|e| e.weight)).map_err(|_| {
+                    anyhow::anyhow!("At least one endpoint needs a positive weight")
+                })?,
+            ),
+        };
+        Ok(Self { endpoints, weights })
+    }
+
+    pub(crate) fn endpoints(&self) -> &[Endpoint] {
+        &self.endpoints
+    }
+
+    pub(crate) fn pick(&self, rng: &mut impl Rng) -> (usize, &Endpoint) {
+        let index = match &self.weights {
+            Some(weights) => weights.sample(rng),
+            None => 0,
+        };
+        (index, &self.endpoints[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    fn endpoint(name: &str, weight: u32) -> Endpoint {
+        Endpoint::new(name, RequestTemplate::get("http://localhost/").unwrap()).with_weight(weight)
+    }
+
+    #[test]
+    fn picks_endpoints_by_weight() {
+        let mix = Mix::new(vec![
+            endpoint("person", 70),
+            endpoint("search", 20),
+            endpoint("write", 10),
+            endpoint("never", 0),
+        ])
+        .unwrap();
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut counts = [0; 4];
+        for _ in 0..10_000 {
+            counts[mix.pick(&mut rng).0] += 1;
+        }
+        assert!((6_700..7_300).contains(&counts[0]), "{:?}", counts);
+        assert!((1_800..2_200).contains(&counts[1]), "{:?}", counts);
+        assert!((800..1_200).contains(&counts[2]), "{:?}", counts);
+        assert_eq!(counts[3], 0);
+
+        assert!(Mix::new(vec![]).is_err());
+        assert!(Mix::new(vec![endpoint("a", 0), endpoint("b", 0)]).is_err());
+    }
+}
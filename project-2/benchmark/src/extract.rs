@@ -0,0 +1,164 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use hyper::{header::HeaderName, HeaderMap};
+use regex::Regex;
+use serde_json::Value;
+
+/// Where the value of a variable is taken from in a response.
+#[derive(Debug, Clone)]
+pub enum Extractor {
+    /// A value of the JSON body, strings are taken without their quotes
+    JsonPath(JsonPath),
+    /// The first value of a header
+    Header(HeaderName),
+    /// The first match in the body, or its first group when the regex has one
+    Regex(Regex),
+}
+
+/// A variable set from every response of a step.
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pub variable: String,
+    pub extractor: Extractor,
+}
+
+impl Extractor {
+    pub(crate) fn needs_body(&self) -> bool {
+        !matches!(self, Extractor::Header(_))
+    }
+
+    // `json` is the body parsed once for every extractor of the step
+    pub(crate) fn extract(
+        &self,
+        headers: &HeaderMap,
+        body: &[u8],
+        json: Option<&Value>,
+    ) -> Option<String> {
+        match self {
+            Extractor::JsonPath(path) => Some(json_text(path.find(json?)?)),
+            Extractor::Header(name) => Some(headers.get(name)?.to_str().ok()?.to_string()),
+            Extractor::Regex(regex) => {
+                let captures = regex.captures(std::str::from_utf8(body).ok()?)?;
+                let found = captures.get(1).or_else(|| captures.get(0))?;
+                Some(found.as_str().to_string())
+            }
+        }
+    }
+}
+
+// Strings without their quotes, other values as written in JSON
+pub(crate) fn json_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Parses `json:$.path`, `header:Name` or `regex:pattern`.
+impl FromStr for Extractor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, rest) = s.split_once(':').with_context(|| {
+            format!(
+                "Extractor {:?} is not in the form 'json:$.path', 'header:Name' or 'regex:pattern'",
+                s
+            )
+        })?;
+        Ok(match kind.trim() {
+            "json" => Extractor::JsonPath(rest.parse()?),
+            "header" => Extractor::Header(
+                HeaderName::from_bytes(rest.trim().as_bytes())
+                    .with_context(|| format!("Invalid header name {:?}", rest))?,
+            ),
+            "regex" => Extractor::Regex(
+                Regex::new(rest).with_context(|| format!("Invalid regex {:?}", rest))?,
+            ),
+            _ => anyhow::bail!(
+                "Unknown extractor {:?}, expected json, header or regex",
+                kind
+            ),
+        })
+    }
+}
+
+/// A path into a JSON document like `$.data.items[0].id`, the leading `$.` is optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+impl JsonPath {
+    pub fn find<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+}
+
+impl FromStr for JsonPath {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("Invalid JSON path {:?}", s);
+        let path = s.trim();
+        let path = path.strip_prefix('$').unwrap_or(path);
+        let mut segments = vec![];
+        for key in path.split('.').filter(|key| !key.is_empty()) {
+            // Indexes follow their key, e.g. items[0][1]
+            let (key, mut indexes) = key.split_at(key.find('[').unwrap_or(key.len()));
+            if !key.is_empty() {
+                segments.push(Segment::Key(key.to_string()));
+            }
+            while !indexes.is_empty() {
+                let (index, rest) = indexes
+                    .strip_prefix('[')
+                    .and_then(|i| i.split_once(']'))
+                    .ok_or_else(invalid)?;
+                segments.push(Segment::Index(index.trim().parse().map_err(|_| invalid())?));
+                indexes = rest;
+            }
+        }
+        Ok(Self { segments })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_from_json_headers_and_regexes() {
+        let body = br#"{"data": {"token": "abc", "items": [{"id": 7}]}}"#;
+        let json: Value = serde_json::from_slice(body).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("location", "/person/42".parse().unwrap());
+        let extract = |extractor: &str| {
+            extractor
+                .parse::<Extractor>()
+                .unwrap()
+                .extract(&headers, body, Some(&json))
+        };
+
+        assert_eq!(extract("json:$.data.token").as_deref(), Some("abc"));
+        assert_eq!(extract("json:data.items[0].id").as_deref(), Some("7"));
+        assert_eq!(extract("json:$.data.missing"), None);
+        assert_eq!(extract("header:Location").as_deref(), Some("/person/42"));
+        assert_eq!(extract("regex:\"id\": (\\d+)").as_deref(), Some("7"));
+        assert_eq!(extract("regex:token"), Some(String::from("token")));
+
+        assert!("xpath://token".parse::<Extractor>().is_err());
+        assert!("json:items[x]".parse::<Extractor>().is_err());
+        assert!("regex:(".parse::<Extractor>().is_err());
+    }
+}
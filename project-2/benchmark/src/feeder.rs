@@ -0,0 +1,182 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rand::{seq::SliceRandom, Rng};
+
+use crate::template::{is_generator, Variables};
+
+/// In which order the rows of a feeder are handed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedOrder {
+    /// In the order of the data, each row once. It always stops once it runs out.
+    Sequential,
+    /// A row picked at random every time. When the feeder stops once it runs
+    /// out, every row is picked once in a random order instead.
+    Random,
+    /// In the order of the data, starting over after the last row. It never runs out.
+    Circular,
+}
+
+/// Who a row of a feeder is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedScope {
+    /// A new row for every request, or every iteration of a scenario
+    Request,
+    /// A row for every virtual user, kept for all of its requests
+    User,
+}
+
+/// Rows of variables the requests are filled in from, e.g. one user ID per row.
+#[derive(Debug)]
+pub struct Feeder {
+    /// Where the rows come from, for error messages
+    pub name: String,
+    rows: Vec<Variables>,
+    order: FeedOrder,
+    scope: FeedScope,
+    stop: bool,
+    // Next row of the sequential orders, shared by every connection
+    cursor: AtomicUsize,
+}
+
+impl Feeder {
+    /// With `stop`, the run ends once every row was handed out. A sequential
+    /// feeder always stops, a circular one cannot.
+    /// Fails without rows, or when the rows do not all have the same columns.
+    pub fn new(
+        name: impl Into<String>,
+        mut rows: Vec<Variables>,
+        order: FeedOrder,
+        scope: FeedScope,
+        stop: bool,
+    ) -> anyhow::Result<Self> {
+        let name = name.into();
+        anyhow::ensure!(!rows.is_empty(), "Feeder {} has no rows", name);
+        anyhow::ensure!(
+            !(stop && order == FeedOrder::Circular),
+            "Feeder {} is circular, it never runs out and cannot stop",
+            name
+        );
+        let stop = stop || order == FeedOrder::Sequential;
+        let columns = |row: &Variables| {
+            let mut columns: Vec<String> = row.keys().cloned().collect();
+            columns.sort();
+            columns
+        };
+        if let Some(i) = rows
+            .iter()
+            .position(|row| columns(row) != columns(&rows[0]))
+        {
+            anyhow::bail!(
+                "Row {} of feeder {} has other columns than the first",
+                i + 1,
+                name
+            );
+        }
+        if let Some(column) = rows[0].keys().find(|column| is_generator(column)) {
+            anyhow::bail!(
+                "Column {:?} of feeder {} has the name of a generated value, rename it",
+                column,
+                name
+            );
+        }
+        if stop && order == FeedOrder::Random {
+            rows.shuffle(&mut rand::thread_rng());
+        }
+        Ok(Self {
+            name,
+            rows,
+            order,
+            scope,
+            stop,
+            cursor: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn scope(&self) -> FeedScope {
+        self.scope
+    }
+
+    /// Names of the variables every row sets.
+    pub fn columns(&self) -> impl Iterator<Item = &str> {
+        self.rows[0].keys().map(String::as_str)
+    }
+
+    // `None` once the feeder ran out and stops
+    pub(crate) fn next(&self, rng: &mut impl Rng) -> Option<&Variables> {
+        if self.order == FeedOrder::Random && !self.stop {
+            return self.rows.choose(rng);
+        }
+        let i = self.cursor.fetch_add(1, Ordering::Relaxed);
+        if self.stop && i >= self.rows.len() {
+            return None;
+        }
+        Some(&self.rows[i % self.rows.len()])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, SeedableRng};
+
+    use super::*;
+
+    fn rows(n: usize) -> Vec<Variables> {
+        (0..n)
+            .map(|i| Variables::from([(String::from("id"), i.to_string())]))
+            .collect()
+    }
+
+    fn take(feeder: &Feeder, n: usize) -> Vec<Option<String>> {
+        let mut rng = SmallRng::seed_from_u64(1);
+        (0..n)
+            .map(|_| feeder.next(&mut rng).map(|row| row["id"].clone()))
+            .collect()
+    }
+
+    #[test]
+    fn hands_out_rows_in_order() {
+        let ids = |ids: &[&str]| {
+            ids.iter()
+                .map(|id| Some(id.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let feeder = Feeder::new(
+            "ids",
+            rows(3),
+            FeedOrder::Circular,
+            FeedScope::Request,
+            false,
+        );
+        assert_eq!(take(&feeder.unwrap(), 4), ids(&["0", "1", "2", "0"]));
+
+        // Sequential feeders run out with or without stop
+        for stop in [false, true] {
+            let feeder = Feeder::new(
+                "ids",
+                rows(3),
+                FeedOrder::Sequential,
+                FeedScope::Request,
+                stop,
+            );
+            let taken = take(&feeder.unwrap(), 4);
+            assert_eq!(taken[..3], ids(&["0", "1", "2"]));
+            assert_eq!(taken[3], None);
+        }
+
+        // Each row once, in any order
+        let feeder = Feeder::new("ids", rows(50), FeedOrder::Random, FeedScope::User, true);
+        let mut taken = take(&feeder.unwrap(), 51);
+        assert_eq!(taken.pop(), Some(None));
+        taken.sort();
+        taken.dedup();
+        assert_eq!(taken.len(), 50);
+
+        assert!(Feeder::new("ids", vec![], FeedOrder::Random, FeedScope::User, false).is_err());
+        assert!(Feeder::new("ids", rows(1), FeedOrder::Circular, FeedScope::User, true).is_err());
+        let mut uneven = rows(2);
+        uneven[1].insert(String::from("name"), String::from("Ada"));
+        assert!(Feeder::new("ids", uneven, FeedOrder::Random, FeedScope::User, false).is_err());
+        let uuids = vec![Variables::from([(String::from("uuid"), String::from("1"))])];
+        assert!(Feeder::new("ids", uuids, FeedOrder::Random, FeedScope::User, false).is_err());
+    }
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use hdrhistogram::Histogram;
+
+use crate::RequestSummary;
+
+/// Significant decimal digits kept by the latency histograms unless configured otherwise.
+pub const DEFAULT_PRECISION: u8 = 3;
+
+/// Latencies of all requests that ended with the same outcome, in microseconds.
+///
+/// Requests are recorded into HDR histograms instead of being kept one by one,
+/// so memory does not grow with the number of requests. Any value read back,
+/// percentiles included, is within a relative error of `10^-precision` of the
+/// recorded one: 0.1% with the default precision of 3. Histograms grow with the
+/// largest latency seen, at precision 3 one covering latencies up to a minute
+/// takes about 140 KB, at precision 2 about 20 KB.
+#[derive(Debug, Clone)]
+pub struct OutcomeStats {
+    /// Time until the whole response body was read
+    pub latency: Histogram<u64>,
+    /// Latency measured from the intended send time, see `RequestSummary::corrected_latency`.
+    /// In the closed model it also holds the requests a slow response held back, see
+    /// `RequestSummary::expected_interval`, so it can count more values than `latency`.
+    pub corrected_latency: Histogram<u64>,
+    pub time_to_first_byte: Histogram<u64>,
+    pub body_bytes: u64,
+}
+
+impl OutcomeStats {
+    /// `precision` is the number of significant decimal digits, from 1 to 5.
+    pub fn new(precision: u8) -> Self {
+        let histogram = Histogram::new(precision).expect("Precision is between 1 and 5");
+        Self {
+            latency: histogram.clone(),
+            corrected_latency: histogram.clone(),
+            time_to_first_byte: histogram,
+            body_bytes: 0,
+        }
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.latency.len()
+    }
+
+    pub fn record(&mut self, summary: &RequestSummary) {
+        self.record_span(
+            summary.latency,
+            summary.corrected_latency(),
+            summary.expected_interval,
+            summary.body_bytes,
+        );
+        record(&mut self.time_to_first_byte, summary.time_to_first_byte);
+    }
+
+    // For what spans several requests, like an iteration of a scenario,
+    // there is no single time to first byte
+    pub(crate) fn record_span(
+        &mut self,
+        latency: Duration,
+        corrected_latency: Duration,
+        expected_interval: Option<Duration>,
+        body_bytes: u64,
+    ) {
+        record(&mut self.latency, latency);
+        match expected_interval {
+            Some(interval) => self
+                .corrected_latency
+                .record_correct(
+                    corrected_latency.as_micros() as u64,
+                    interval.as_micros() as u64,
+                )
+                .expect("Resizable histogram"),
+            None => record(&mut self.corrected_latency, corrected_latency),
+        }
+        self.body_bytes += body_bytes;
+    }
+
+    pub fn add(&mut self, other: &OutcomeStats) {
+        self.latency
+            .add(&other.latency)
+            .expect("Resizable histogram");
+        self.corrected_latency
+            .add(&other.corrected_latency)
+            .expect("Resizable histogram");
+        self.time_to_first_byte
+            .add(&other.time_to_first_byte)
+            .expect("Resizable histogram");
+        self.body_bytes += other.body_bytes;
+    }
+}
+
+// Histograms resize themselves, so recording never runs out of range
+fn record(histogram: &mut Histogram<u64>, duration: Duration) {
+    histogram
+        .record(duration.as_micros() as u64)
+        .expect("Resizable histogram");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::Outcome;
+
+    fn summary(latency: Duration) -> RequestSummary {
+        let now = Instant::now();
+        RequestSummary {
+            intended_at: now,
+            started_at: now,
+            latency,
+            time_to_first_byte: latency / 2,
+            expected_interval: None,
+            body_bytes: 10,
+            outcome: Outcome::Status(200),
+        }
+    }
+
+    #[test]
+    fn percentiles_stay_within_precision() {
+        let mut stats = OutcomeStats::new(DEFAULT_PRECISION);
+        for i in 1..=10_000 {
+            stats.record(&summary(Duration::from_micros(i * 100)));
+        }
+
+        let p99 = stats.latency.value_at_quantile(0.99) as f64;
+        assert!((p99 - 990_000_f64).abs() <= 990.0);
+        assert_eq!(stats.requests(), 10_000);
+        assert_eq!(stats.body_bytes, 100_000);
+    }
+
+    #[test]
+    fn memory_does_not_grow_with_requests() {
+        let mut stats = OutcomeStats::new(DEFAULT_PRECISION);
+        stats.record(&summary(Duration::from_secs(60)));
+        let size = stats.latency.distinct_values();
+        for _ in 0..100_000 {
+            stats.record(&summary(Duration::from_millis(20)));
+        }
+        assert_eq!(stats.latency.distinct_values(), size);
+    }
+
+    #[test]
+    fn corrects_for_held_back_requests() {
+        let mut stats = OutcomeStats::new(DEFAULT_PRECISION);
+        for _ in 0..98 {
+            stats.record(&summary(Duration::from_millis(10)));
+        }
+        // A 1s stall held back the 99 requests a 10ms pace would have sent meanwhile
+        let mut stalled = summary(Duration::from_secs(1));
+        stalled.expected_interval = Some(Duration::from_millis(10));
+        stats.record(&stalled);
+
+        assert_eq!(stats.requests(), 99);
+        assert_eq!(stats.corrected_latency.len(), 99 + 99);
+        assert!(stats.latency.value_at_quantile(0.9) < 11_000);
+        assert!(stats.corrected_latency.value_at_quantile(0.9) > 500_000);
+    }
+
+    #[test]
+    fn adds_up_histograms() {
+        let mut first = OutcomeStats::new(DEFAULT_PRECISION);
+        first.record(&summary(Duration::from_millis(1)));
+        let mut second = OutcomeStats::new(DEFAULT_PRECISION);
+        second.record(&summary(Duration::from_secs(5)));
+
+        first.add(&second);
+        assert_eq!(first.requests(), 2);
+        assert_eq!(first.latency.max(), second.latency.max());
+        assert_eq!(first.body_bytes, 20);
+    }
+}
@@ -1,104 +1,685 @@
 use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    fmt::{self, Display},
+    hash::Hash,
+    io,
     str::FromStr,
-    time::{Duration, Instant},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{Context, Ok};
 use async_trait::async_trait;
-use hyper::{client::HttpConnector, Client, Uri};
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use futures::future::join_all;
+use hyper::Uri;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use tokio::sync::{
+    mpsc::{channel, unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+    Mutex, OwnedSemaphorePermit, Semaphore,
+};
+use tokio::time::{error::Elapsed, interval_at, Interval, MissedTickBehavior};
+
+mod check;
+mod client;
+mod endpoint;
+mod extract;
+mod feeder;
+mod histogram;
+mod request;
+mod scenario;
+mod snapshot;
+mod stage;
+mod status;
+mod template;
+mod tls;
+use check::run_checks;
+pub use check::{Check, CheckCount, Condition};
+use client::{ClientSettings, HttpClient, Requester, Response};
+pub use endpoint::Endpoint;
+use endpoint::Mix;
+pub use extract::{Extraction, Extractor, JsonPath};
+pub use feeder::{FeedOrder, FeedScope, Feeder};
+pub use histogram::{OutcomeStats, DEFAULT_PRECISION};
+pub use request::RequestTemplate;
+pub use scenario::{IterationOutcome, Scenario, Step};
+pub use snapshot::Snapshot;
+use snapshot::Window;
+use stage::Timeline;
+pub use stage::{Stage, StageTarget};
+pub use status::StatusSet;
+pub use template::{Template, Variables};
+pub use tls::TlsSettings;
 
 pub struct BenchmarkSettings {
     pub connections: u16,
     pub requests: u64,
-    pub target_uri: Uri,
+    /// When set, every connection keeps sending requests until this much time
+    /// has passed since the start of the run and `requests` is ignored.
+    pub duration: Option<Duration>,
+    /// When set, requests are sent at this fixed rate (requests per second)
+    /// regardless of response times, and `connections` is the size of the
+    /// worker pool they are dispatched to.
+    pub rate: Option<u32>,
+    /// Requests without a response after this long are recorded as timed out
+    pub request_timeout: Option<Duration>,
+    pub connect_timeout: Option<Duration>,
+    pub tls: TlsSettings,
+    /// Speak HTTP/2, with prior knowledge for `http://` and ALPN for `https://` targets
+    pub http2: bool,
+    /// Concurrent requests multiplexed on each connection, only above 1 with HTTP/2
+    pub streams: u16,
+    /// Significant decimal digits kept by the latency histograms, from 1 to 5
+    pub latency_precision: u8,
+    /// Load profile run one stage after the other. When set, the run lasts as
+    /// long as the stages, `requests` and `duration` are ignored, and so are
+    /// `connections` when the stages target connections or `rate` when they target a rate.
+    pub stages: Vec<Stage>,
+    pub stage_target: StageTarget,
+    /// When set, a `Snapshot` of every interval of this length is passed to
+    /// `BenchmarkStats::snapshot` as the run goes and kept in the result
+    pub snapshot_interval: Option<Duration>,
+    /// When set, every request is sent here as soon as it completes. The channel
+    /// is bounded so a slow receiver slows the run down instead of growing memory.
+    pub events: Option<Sender<RequestEvent>>,
+    /// Every request goes to one of them, picked at random by weight
+    pub endpoints: Vec<Endpoint>,
+    /// When set, the endpoints are ignored and every connection runs the steps of
+    /// the scenario instead. `requests` and `rate` then count iterations, not requests.
+    pub scenario: Option<Scenario>,
+    /// Rows the `{{name}}` placeholders of the requests are filled in from. The run
+    /// ends early when a feeder that stops runs out.
+    pub feeders: Vec<Feeder>,
+    /// Statuses counted as successes, any other status or an error is a failure
+    pub success_statuses: StatusSet,
 }
 
 #[derive(Debug)]
 pub struct BenchmarkResult {
     pub target_uri: Uri,
+    /// Wall clock time the run started at
+    pub started_at: SystemTime,
     pub total_time: Duration,
-    pub request_summaries: Vec<RequestSummary>,
+    /// Latencies and body sizes per status code or kind of error
+    pub outcomes: HashMap<Outcome, OutcomeStats>,
+    /// Statuses the run counted as successes
+    pub success_statuses: StatusSet,
+    /// The same breakdown for each endpoint, or each step of the scenario,
+    /// in the order of the settings
+    pub endpoints: Vec<EndpointSummary>,
+    /// Duration of whole iterations of the scenario by how they ended, empty without one
+    pub iterations: HashMap<IterationOutcome, OutcomeStats>,
+    /// Responses that passed and failed each check, by the name of the check
+    pub checks: HashMap<String, CheckCount>,
+    /// Responses that failed at least one check. They still count under their status,
+    /// apart from the requests that got no response at all.
+    pub failed_checks: u64,
+    /// The same breakdown for each stage of the load profile, empty without stages
+    pub stages: Vec<StageSummary>,
+    /// Rate, errors and latency percentiles of every interval of the run
+    pub snapshots: Vec<Snapshot>,
+    /// The rate the scheduler was asked for, only set in rate mode
+    pub intended_rate: Option<u32>,
+    /// Requests the scheduler could not send on time because every worker was busy
+    pub late_requests: u64,
+    /// Requests sent by each connection, to make load imbalance visible
+    pub connection_requests: Vec<u64>,
+    /// Duration of every TLS handshake, connections are reused so there are
+    /// usually far fewer handshakes than requests
+    pub tls_handshakes: Vec<Duration>,
+    /// Responses per HTTP version the server actually answered with
+    pub protocols: BTreeMap<String, u64>,
 }
 
 impl BenchmarkResult {
     pub fn new(target_uri: Uri) -> Self {
         Self {
             target_uri,
+            started_at: SystemTime::now(),
             total_time: Duration::from_secs(0),
-            request_summaries: vec![],
+            outcomes: HashMap::new(),
+            success_statuses: StatusSet::default(),
+            endpoints: vec![],
+            iterations: HashMap::new(),
+            checks: HashMap::new(),
+            failed_checks: 0,
+            stages: vec![],
+            snapshots: vec![],
+            intended_rate: None,
+            late_requests: 0,
+            connection_requests: vec![],
+            tls_handshakes: vec![],
+            protocols: BTreeMap::new(),
         }
     }
 
     pub fn combine_conn_summaries(&mut self, conn_summaries: Vec<ConnectionSummary>) {
         for r in conn_summaries {
-            self.request_summaries.extend(r.request_summaries);
+            self.connection_requests.push(r.total_requests);
+            self.tls_handshakes.extend(r.tls_handshakes);
+            for (protocol, n) in r.protocols {
+                *self.protocols.entry(protocol).or_default() += n;
+            }
+            for (outcome, stats) in r.iterations {
+                add_outcome(&mut self.iterations, outcome, &stats);
+            }
+            for (name, count) in r.checks {
+                self.checks.entry(name).or_default().add(&count);
+            }
+            self.failed_checks += r.failed_checks;
+            for (i, outcomes) in r.outcomes.into_iter().enumerate() {
+                for ((endpoint, outcome), stats) in outcomes {
+                    if let Some(stage) = self.stages.get_mut(i) {
+                        add_outcome(&mut stage.outcomes, outcome, &stats);
+                    }
+                    if let Some(endpoint) = self.endpoints.get_mut(endpoint) {
+                        add_outcome(&mut endpoint.outcomes, outcome, &stats);
+                    }
+                    add_outcome(&mut self.outcomes, outcome, &stats);
+                }
+            }
+        }
+    }
+
+    pub fn total_requests(&self) -> u64 {
+        self.outcomes.values().map(OutcomeStats::requests).sum()
+    }
+
+    /// Requests answered with one of the success statuses
+    pub fn success_requests(&self) -> u64 {
+        self.outcomes
+            .iter()
+            .filter(|(outcome, _)| outcome.is_success(&self.success_statuses))
+            .map(|(_, stats)| stats.requests())
+            .sum()
+    }
+
+    /// Percent of the requests that were not successful
+    pub fn error_rate(&self) -> f64 {
+        match self.total_requests() {
+            0 => 0_f64,
+            total => (total - self.success_requests()) as f64 * 100_f64 / total as f64,
+        }
+    }
+}
+
+fn add_outcome<K: Eq + Hash>(into: &mut HashMap<K, OutcomeStats>, key: K, stats: &OutcomeStats) {
+    match into.entry(key) {
+        Entry::Occupied(mut entry) => entry.get_mut().add(stats),
+        Entry::Vacant(entry) => {
+            entry.insert(stats.clone());
         }
     }
 }
 
 #[derive(Debug)]
+pub struct EndpointSummary {
+    pub name: String,
+    pub outcomes: HashMap<Outcome, OutcomeStats>,
+}
+
+#[derive(Debug)]
+pub struct StageSummary {
+    pub stage: Stage,
+    /// Requests are counted in the stage they were intended to be sent in
+    pub outcomes: HashMap<Outcome, OutcomeStats>,
+}
+
+#[derive(Debug, Default)]
 pub struct ConnectionSummary {
     total_requests: u64,
     success_requests: u64,
     fail_requests: u64,
-    request_summaries: Vec<RequestSummary>,
+    // One map per stage, a single one without stages,
+    // keyed by the index of the endpoint or step and the outcome
+    outcomes: Vec<HashMap<(usize, Outcome), OutcomeStats>>,
+    iterations: HashMap<IterationOutcome, OutcomeStats>,
+    checks: HashMap<String, CheckCount>,
+    failed_checks: u64,
+    tls_handshakes: Vec<Duration>,
+    protocols: BTreeMap<String, u64>,
+}
+
+impl ConnectionSummary {
+    // Adds up the summaries of the streams sharing a connection
+    fn merge(&mut self, other: ConnectionSummary) {
+        self.total_requests += other.total_requests;
+        self.success_requests += other.success_requests;
+        self.fail_requests += other.fail_requests;
+        if self.outcomes.len() < other.outcomes.len() {
+            self.outcomes
+                .resize_with(other.outcomes.len(), HashMap::new);
+        }
+        for (outcomes, other) in self.outcomes.iter_mut().zip(other.outcomes) {
+            for (key, stats) in other {
+                add_outcome(outcomes, key, &stats);
+            }
+        }
+        for (outcome, stats) in other.iterations {
+            add_outcome(&mut self.iterations, outcome, &stats);
+        }
+        for (name, count) in other.checks {
+            self.checks.entry(name).or_default().add(&count);
+        }
+        self.failed_checks += other.failed_checks;
+        self.tls_handshakes.extend(other.tls_handshakes);
+        for (protocol, n) in other.protocols {
+            *self.protocols.entry(protocol).or_default() += n;
+        }
+    }
 }
 
+/// A single request, recorded into the `OutcomeStats` of its outcome once it completes.
 #[derive(Debug)]
 pub struct RequestSummary {
+    /// When the request should have been sent. Only the rate scheduler can
+    /// send requests late, in the closed model this equals `started_at`.
+    pub intended_at: Instant,
+    pub started_at: Instant,
+    /// In the closed model, the average time between the requests of the stream
+    /// so far. A response slower than that held back the requests the stream would
+    /// have sent meanwhile, they are added to the corrected latencies as if they
+    /// had waited for it (coordinated omission). `None` for requests sent on a schedule.
+    pub expected_interval: Option<Duration>,
+    /// Time until the whole response body was read
     pub latency: Duration,
-    pub status_code: u16,
+    /// Time until the response headers arrived
+    pub time_to_first_byte: Duration,
+    pub body_bytes: u64,
+    pub outcome: Outcome,
 }
 
-#[async_trait]
-trait Requester {
-    async fn get(&self, uri: Uri) -> anyhow::Result<u16>;
+impl RequestSummary {
+    /// Latency measured from the intended send time, so it includes the time the
+    /// request waited for a worker while the server stalled (coordinated omission).
+    pub fn corrected_latency(&self) -> Duration {
+        self.latency + self.started_at.saturating_duration_since(self.intended_at)
+    }
 }
 
-struct HttpClient(Client<HttpConnector>);
+/// A completed request as sent to `BenchmarkSettings::events`.
+#[derive(Debug)]
+pub struct RequestEvent {
+    /// Wall clock time the request was sent at
+    pub timestamp: SystemTime,
+    /// Index of the connection that sent it
+    pub connection: u16,
+    /// Index of the endpoint it was sent to in `BenchmarkSettings::endpoints`,
+    /// or of its step in the scenario
+    pub endpoint: usize,
+    pub summary: RequestSummary,
+}
 
-impl HttpClient {
-    fn new() -> Self {
-        HttpClient(Client::new())
+// Timestamps the requests of every connection and passes them on
+struct EventLog {
+    tx: Sender<RequestEvent>,
+    start: Instant,
+    started_at: SystemTime,
+}
+
+impl EventLog {
+    async fn send(&self, connection: u16, endpoint: usize, summary: RequestSummary) {
+        let event = RequestEvent {
+            timestamp: self.started_at + summary.started_at.saturating_duration_since(self.start),
+            connection,
+            endpoint,
+            summary,
+        };
+        // Nobody listens any more, the run goes on without the log
+        let _ = self.tx.send(event).await;
     }
 }
 
-#[async_trait]
-impl Requester for HttpClient {
-    async fn get(&self, uri: Uri) -> anyhow::Result<u16> {
-        let status = self.0.get(uri.clone()).await?.status().as_u16();
-        Ok(status)
+/// How a request ended: either the server answered with a status code
+/// or the request failed before a response arrived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Outcome {
+    Status(u16),
+    Error(RequestError),
+}
+
+impl Outcome {
+    pub fn is_success(&self, statuses: &StatusSet) -> bool {
+        matches!(self, Outcome::Status(status) if statuses.contains(*status))
+    }
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Status(status) => write!(f, "{}", status),
+            Outcome::Error(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestError {
+    ConnectRefused,
+    ConnectionReset,
+    Timeout,
+    Dns,
+    Tls,
+    Protocol,
+    // The values of the feeders or of earlier steps did not make a valid request,
+    // it was not sent
+    Invalid,
+    Other,
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            RequestError::ConnectRefused => "connect_refused",
+            RequestError::ConnectionReset => "connection_reset",
+            RequestError::Timeout => "timeout",
+            RequestError::Dns => "dns",
+            RequestError::Tls => "tls",
+            RequestError::Protocol => "protocol",
+            RequestError::Invalid => "invalid_request",
+            RequestError::Other => "other",
+        };
+        f.write_str(name)
+    }
+}
+
+impl RequestError {
+    // Walks the error chain looking for the io or hyper error that caused it
+    fn classify(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            // hyper's connector reports resolver failures with this prefix
+            if cause.to_string().starts_with("dns error") {
+                return RequestError::Dns;
+            }
+            if cause.is::<Elapsed>() {
+                return RequestError::Timeout;
+            }
+            if let Some(io_err) = cause.downcast_ref::<io::Error>() {
+                // rustls reports handshake failures wrapped in an io error
+                if io_err.get_ref().is_some_and(|e| e.is::<rustls::Error>()) {
+                    return RequestError::Tls;
+                }
+                match io_err.kind() {
+                    io::ErrorKind::ConnectionRefused => return RequestError::ConnectRefused,
+                    io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe => return RequestError::ConnectionReset,
+                    io::ErrorKind::TimedOut => return RequestError::Timeout,
+                    _ => {}
+                }
+            }
+        }
+        match err.downcast_ref::<hyper::Error>() {
+            Some(hyper_err) if hyper_err.is_timeout() => RequestError::Timeout,
+            Some(hyper_err) if hyper_err.is_incomplete_message() => RequestError::ConnectionReset,
+            Some(hyper_err) if hyper_err.is_connect() => RequestError::Other,
+            Some(_) => RequestError::Protocol,
+            None => RequestError::Other,
+        }
     }
 }
 
 struct ConnectionSettings {
-    requests: u64,
-    target_uri: Uri,
+    id: u16,
+    pacing: Pacing,
+    streams: u16,
+    precision: u8,
+    success: StatusSet,
+    timeline: Arc<Timeline>,
+    window: Option<Arc<Window>>,
+    events: Option<Arc<EventLog>>,
+    workload: Arc<Workload>,
+}
+
+// What a connection sends every time it may send
+struct Workload {
+    requests: Requests,
+    feeders: Vec<Feeder>,
+}
+
+enum Requests {
+    // A single request to one of the endpoints
+    Endpoints(Mix),
+    // Every step of the scenario, one after the other
+    Scenario(Scenario),
+}
+
+impl Workload {
+    // Names the statistics are broken down by
+    fn names(&self) -> Vec<String> {
+        match &self.requests {
+            Requests::Endpoints(mix) => mix.endpoints().iter().map(|e| e.name.clone()).collect(),
+            Requests::Scenario(scenario) => {
+                scenario.steps().iter().map(|s| s.name.clone()).collect()
+            }
+        }
+    }
+
+    // The URI of the first request is only known up front when it has no placeholder
+    fn target(&self) -> Uri {
+        let request = match &self.requests {
+            Requests::Endpoints(mix) => &mix.endpoints()[0].request,
+            Requests::Scenario(scenario) => &scenario.steps()[0].request,
+        };
+        Some(&request.uri)
+            .filter(|uri| uri.is_constant())
+            .and_then(|uri| uri.render(&Variables::new()).ok())
+            .and_then(|uri| uri.parse().ok())
+            .unwrap_or_default()
+    }
+
+    // Every variable of the requests must come from somewhere
+    fn check(&self) -> anyhow::Result<()> {
+        let provided: Vec<&str> = self.feeders.iter().flat_map(Feeder::columns).collect();
+        match &self.requests {
+            Requests::Endpoints(mix) => {
+                for endpoint in mix.endpoints() {
+                    if let Some(variable) =
+                        endpoint.request.variables().find(|v| !provided.contains(v))
+                    {
+                        anyhow::bail!(
+                            "Endpoint {:?} uses {{{{{}}}}}, which no feeder provides",
+                            endpoint.name,
+                            variable
+                        );
+                    }
+                }
+                Ok(())
+            }
+            Requests::Scenario(scenario) => scenario.check(&provided),
+        }
+    }
+
+    // Adds a row of every feeder of the scope, `false` once one of them ran out
+    fn feed(&self, scope: FeedScope, rng: &mut impl Rng, variables: &mut Variables) -> bool {
+        for feeder in self.feeders.iter().filter(|f| f.scope() == scope) {
+            match feeder.next(rng) {
+                Some(row) => variables.extend(row.iter().map(|(k, v)| (k.clone(), v.clone()))),
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+#[derive(Clone)]
+enum Pacing {
+    // Closed model: connections take requests from a shared budget,
+    // the next request goes out as soon as the previous one returns
+    Budget(Arc<AtomicU64>),
+    // Closed model until the deadline is reached
+    Deadline(Instant),
+    // Open model: the connection waits for the scheduler to dispatch a request
+    Open(Arc<Mutex<UnboundedReceiver<Dispatch>>>),
+    // Closed model following the stages: the connection only sends while
+    // the target number of connections is at least this level
+    Ramp(Arc<Timeline>, f64),
+}
+
+// A request a connection is allowed to send.
+// In the open model it holds the worker's permit while the request is in flight,
+// so the scheduler can tell that every worker is busy
+struct Dispatch {
+    intended_at: Instant,
+    // Only in the closed model, the stream sets it to the pace it kept so far
+    expected_interval: Option<Duration>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Dispatch {
+    fn now() -> Self {
+        Self {
+            intended_at: Instant::now(),
+            expected_interval: None,
+            _permit: None,
+        }
+    }
+}
+
+// The schedule a stream of the closed model is expected to keep: the average
+// time between its requests since the first one
+#[derive(Default)]
+struct Pace {
+    first: Option<Instant>,
+    dispatches: u32,
+}
+
+impl Pace {
+    // Counts a dispatch and returns the interval expected before it,
+    // `None` for the first one
+    fn next(&mut self, at: Instant) -> Option<Duration> {
+        let first = *self.first.get_or_insert(at);
+        let interval =
+            (self.dispatches > 0).then(|| at.saturating_duration_since(first) / self.dispatches);
+        self.dispatches = self.dispatches.saturating_add(1);
+        interval
+    }
 }
 
 impl ConnectionSettings {
-    fn from(value: &BenchmarkSettings) -> Self {
+    fn from(
+        id: u16,
+        value: &BenchmarkSettings,
+        pacing: Pacing,
+        timeline: Arc<Timeline>,
+        window: Option<Arc<Window>>,
+        events: Option<Arc<EventLog>>,
+        workload: Arc<Workload>,
+    ) -> Self {
         Self {
-            requests: value.requests / value.connections as u64,
-            target_uri: value.target_uri.clone(),
+            id,
+            pacing,
+            streams: value.streams.max(1),
+            precision: value.latency_precision,
+            success: value.success_statuses.clone(),
+            timeline,
+            window,
+            events,
+            workload,
         }
     }
+
+    // Waits until the connection may send its next request,
+    // `None` means the connection is done
+    async fn next(&self) -> Option<Dispatch> {
+        match &self.pacing {
+            Pacing::Budget(remaining) => remaining
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                .ok()
+                .map(|_| Dispatch::now()),
+            Pacing::Deadline(deadline) => (Instant::now() < *deadline).then(Dispatch::now),
+            Pacing::Open(queue) => queue.lock().await.recv().await,
+            Pacing::Ramp(timeline, level) => {
+                let active_at = timeline.reaches(*level, timeline.start().elapsed())?;
+                tokio::time::sleep_until((timeline.start() + active_at).into()).await;
+                Some(Dispatch::now())
+            }
+        }
+    }
+}
+
+struct ScheduleSettings {
+    rate: u32,
+    requests: u64,
+    deadline: Option<Instant>,
+    // When set the rate follows the stages instead, until the last one ends
+    timeline: Option<Arc<Timeline>>,
 }
 
-pub fn build_uri(s: &String) -> Uri {
+// Hands out requests at a fixed rate to idle workers.
+// A request that is due while every worker is busy is counted as late and waits
+// for the next free worker, keeping its intended send time.
+// Returns the number of late requests
+async fn schedule_task(
+    settings: ScheduleSettings,
+    idle_workers: Arc<Semaphore>,
+    queue: UnboundedSender<Dispatch>,
+) -> u64 {
+    let start = Instant::now();
+    let mut late = 0;
+    let mut elapsed = Duration::ZERO;
+    for i in 0.. {
+        // Computed from the start so timer jitter does not accumulate,
+        // a late tick is followed by a burst until the schedule is caught up
+        let intended_at = match &settings.timeline {
+            Some(timeline) => match timeline.next_arrival(elapsed) {
+                Some(next) => {
+                    elapsed = next;
+                    timeline.start() + next
+                }
+                None => break,
+            },
+            None => start + Duration::from_secs_f64(i as f64 / settings.rate as f64),
+        };
+        let done = match settings.deadline {
+            Some(deadline) => intended_at >= deadline,
+            None => i >= settings.requests,
+        };
+        if done {
+            break;
+        }
+
+        tokio::time::sleep_until(intended_at.into()).await;
+        let permit = match idle_workers.clone().try_acquire_owned() {
+            Result::Ok(permit) => permit,
+            Err(_) => {
+                late += 1;
+                match idle_workers.clone().acquire_owned().await {
+                    Result::Ok(permit) => permit,
+                    Err(_) => break,
+                }
+            }
+        };
+        let dispatch = Dispatch {
+            intended_at,
+            expected_interval: None,
+            _permit: Some(permit),
+        };
+        if queue.send(dispatch).is_err() {
+            break;
+        }
+    }
+    late
+}
+
+pub fn build_uri(s: &str) -> Uri {
     Uri::from_str(s).expect("Unparsable target URI")
 }
 
 pub trait BenchmarkStats {
     fn update(&self, n: u64);
     fn finish(&self);
+
+    /// Called at the end of every `BenchmarkSettings::snapshot_interval`
+    fn snapshot(&self, _snapshot: &Snapshot) {}
 }
 
 #[async_trait]
 trait TaskStats {
-    async fn update(&self, n: u64) -> ();
-    async fn finish(&self) -> ();
+    async fn update(&self, n: u64);
+    async fn finish(&self);
 }
 
 struct TaskNotifier {
@@ -107,16 +688,12 @@ struct TaskNotifier {
 
 #[async_trait]
 impl TaskStats for TaskNotifier {
-    async fn update(&self, n: u64) -> () {
-        match self.tx.send(n).await {
-            _ => (),
-        }
+    async fn update(&self, n: u64) {
+        let _ = self.tx.send(n).await;
     }
 
-    async fn finish(&self) -> () {
-        match self.tx.send(0).await {
-            _ => (),
-        }
+    async fn finish(&self) {
+        let _ = self.tx.send(0).await;
     }
 }
 
@@ -128,37 +705,169 @@ impl TaskNotifier {
 
 pub async fn run(
     process: impl BenchmarkStats,
-    benchmark_settings: BenchmarkSettings,
+    mut benchmark_settings: BenchmarkSettings,
 ) -> anyhow::Result<BenchmarkResult> {
-    let mut result = BenchmarkResult::new(benchmark_settings.target_uri.clone());
-    let (tx, mut rx) = TaskNotifier::init_channel(benchmark_settings.connections.into());
+    anyhow::ensure!(
+        (1..=5).contains(&benchmark_settings.latency_precision),
+        "Latency precision must be between 1 and 5 digits"
+    );
+    let workload = Workload {
+        requests: match benchmark_settings.scenario.clone() {
+            Some(scenario) => Requests::Scenario(scenario),
+            None => Requests::Endpoints(Mix::new(benchmark_settings.endpoints.clone())?),
+        },
+        feeders: std::mem::take(&mut benchmark_settings.feeders),
+    };
+    workload.check()?;
+    let workload = Arc::new(workload);
+    let mut result = BenchmarkResult::new(workload.target());
+    result.success_statuses = benchmark_settings.success_statuses.clone();
+    result.endpoints = workload
+        .names()
+        .into_iter()
+        .map(|name| EndpointSummary {
+            name,
+            outcomes: HashMap::new(),
+        })
+        .collect();
+    let client_settings =
+        ClientSettings::from(&benchmark_settings).context("Error loading TLS settings")?;
+
+    let staged = !benchmark_settings.stages.is_empty();
+    let ramp_connections = staged && benchmark_settings.stage_target == StageTarget::Connections;
+    let ramp_rate = staged && benchmark_settings.stage_target == StageTarget::Rate;
+    // Connections following the stages are all spawned up front but only
+    // start sending once the profile reaches them
+    let connections = if ramp_connections {
+        let peak = benchmark_settings.stages.iter().map(Stage::peak).max();
+        u16::try_from(peak.unwrap_or_default()).context("Too many connections in the stages")?
+    } else {
+        benchmark_settings.connections
+    };
+    anyhow::ensure!(connections > 0, "The stages never open a connection");
+    result.stages = benchmark_settings
+        .stages
+        .iter()
+        .map(|&stage| StageSummary {
+            stage,
+            outcomes: HashMap::new(),
+        })
+        .collect();
+    let (tx, mut rx) = TaskNotifier::init_channel(connections.into());
 
     let now = Instant::now();
+    result.started_at = SystemTime::now();
+    let timeline = Arc::new(Timeline::new(now, benchmark_settings.stages.clone()));
+    let deadline = if staged {
+        Some(now + timeline.total())
+    } else {
+        benchmark_settings.duration.map(|d| now + d)
+    };
+
+    let mut schedule_future = None;
+    let pacing = if benchmark_settings.rate.is_some() || ramp_rate {
+        let (queue_tx, queue_rx) = unbounded_channel();
+        // Every stream of every connection is a worker
+        let workers = connections as usize * benchmark_settings.streams.max(1) as usize;
+        let idle_workers = Arc::new(Semaphore::new(workers));
+        schedule_future = Some(tokio::spawn(schedule_task(
+            ScheduleSettings {
+                rate: benchmark_settings.rate.unwrap_or_default(),
+                requests: benchmark_settings.requests,
+                deadline,
+                timeline: ramp_rate.then(|| timeline.clone()),
+            },
+            idle_workers,
+            queue_tx,
+        )));
+        if !ramp_rate {
+            result.intended_rate = benchmark_settings.rate;
+        }
+        Pacing::Open(Arc::new(Mutex::new(queue_rx)))
+    } else if let Some(deadline) = deadline {
+        Pacing::Deadline(deadline)
+    } else {
+        Pacing::Budget(Arc::new(AtomicU64::new(benchmark_settings.requests)))
+    };
+
+    let window = benchmark_settings.snapshot_interval.map(|_| {
+        Arc::new(Window::new(
+            now,
+            benchmark_settings.latency_precision,
+            connections,
+        ))
+    });
+    let mut ticker = benchmark_settings.snapshot_interval.map(|period| {
+        let mut ticker = interval_at((now + period).into(), period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        ticker
+    });
+
+    let events = benchmark_settings.events.clone().map(|tx| {
+        Arc::new(EventLog {
+            tx,
+            start: now,
+            started_at: result.started_at,
+        })
+    });
 
     let mut conn_futures: Vec<_> = vec![];
-    for _ in 0..benchmark_settings.connections {
+    for i in 0..connections {
+        let pacing = if ramp_connections {
+            Pacing::Ramp(timeline.clone(), f64::from(i) + 1_f64)
+        } else {
+            pacing.clone()
+        };
         conn_futures.push(tokio::spawn(connection_task(
-            HttpClient::new(),
+            HttpClient::new(&client_settings),
             TaskNotifier { tx: tx.clone() },
-            ConnectionSettings::from(&benchmark_settings),
+            ConnectionSettings::from(
+                i,
+                &benchmark_settings,
+                pacing,
+                timeline.clone(),
+                window.clone(),
+                events.clone(),
+                workload.clone(),
+            ),
         )));
     }
 
     let mut count_channel_closed = 0;
     loop {
-        if let Some(n) = rx.recv().await {
-            process.update(n);
-            if n == 0 {
-                count_channel_closed += 1;
+        tokio::select! {
+            Some(n) = rx.recv() => {
+                process.update(n);
+                if n == 0 {
+                    count_channel_closed += 1;
+                }
+            }
+            _ = tick(&mut ticker) => {
+                if let Some(window) = &window {
+                    let snapshot = window.take();
+                    process.snapshot(&snapshot);
+                    result.snapshots.push(snapshot);
+                }
             }
         }
 
-        if count_channel_closed >= benchmark_settings.connections {
+        if count_channel_closed >= connections {
             break;
         }
     }
 
     result.total_time = now.elapsed();
+    // The last interval ends with the run
+    if let (Some(window), Some(period)) = (&window, benchmark_settings.snapshot_interval) {
+        if let Some(snapshot) = window.finish(period) {
+            process.snapshot(&snapshot);
+            result.snapshots.push(snapshot);
+        }
+    }
+
+    if let Some(f) = schedule_future {
+        result.late_requests = f.await.context("Error spawning scheduler task")?;
+    }
 
     let mut conn_summaries: Vec<ConnectionSummary> = Vec::with_capacity(conn_futures.len());
     for f in conn_futures {
@@ -174,32 +883,99 @@ pub async fn run(
     Ok(result)
 }
 
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
 async fn connection_task(
     client: impl Requester,
     stats: impl TaskStats,
     conn_setting: ConnectionSettings,
 ) -> anyhow::Result<ConnectionSummary> {
-    let mut summary = ConnectionSummary {
-        success_requests: 0,
-        total_requests: 0,
-        fail_requests: 0,
-        request_summaries: vec![],
-    };
+    // With HTTP/2 the streams share the client's single connection,
+    // otherwise there is exactly one stream
+    let streams = (0..conn_setting.streams).map(|_| stream_task(&client, &stats, &conn_setting));
+    let mut summary = ConnectionSummary::default();
+    for stream_summary in join_all(streams).await {
+        summary.merge(stream_summary);
+    }
 
+    summary.tls_handshakes = client.take_tls_handshakes();
+    // notify finished
+    stats.finish().await;
+
+    Ok(summary)
+}
+
+async fn stream_task(
+    client: &impl Requester,
+    stats: &impl TaskStats,
+    conn_setting: &ConnectionSettings,
+) -> ConnectionSummary {
+    let mut summary = ConnectionSummary::default();
+    let mut rng = SmallRng::from_entropy();
+    let workload = &*conn_setting.workload;
+
+    // The stream is a virtual user, it keeps the rows of the feeders for users
+    let mut user_variables = Variables::new();
+    if !workload.feed(FeedScope::User, &mut rng, &mut user_variables) {
+        return summary;
+    }
+
+    let mut pace = Pace::default();
     let mut queue_stats = 0;
-    for _ in 0..conn_setting.requests {
-        let now = Instant::now();
-        let status_code = client.get(conn_setting.target_uri.clone()).await?;
-        summary.request_summaries.push(RequestSummary {
-            latency: now.elapsed(),
-            status_code,
-        });
-        match status_code {
-            200 => summary.success_requests += 1,
-            _ => summary.fail_requests += 1,
+    while let Some(mut dispatch) = conn_setting.next().await {
+        let mut variables = user_variables.clone();
+        if !workload.feed(FeedScope::Request, &mut rng, &mut variables) {
+            break;
+        }
+        // Requests sent on a schedule are corrected by their intended send time instead
+        if !matches!(conn_setting.pacing, Pacing::Open(_)) {
+            dispatch.expected_interval = pace.next(dispatch.intended_at);
+        }
+        // Requests are counted in the stage they were intended to be sent in
+        let stage = conn_setting.timeline.stage_index(dispatch.intended_at);
+        match &workload.requests {
+            Requests::Endpoints(mix) => {
+                let (i, endpoint) = mix.pick(&mut rng);
+                let (request_summary, response) = send(
+                    client,
+                    &endpoint.request,
+                    &variables,
+                    endpoint.needs_body(),
+                    dispatch.intended_at,
+                    dispatch.expected_interval,
+                )
+                .await;
+                record(
+                    conn_setting,
+                    &mut summary,
+                    stage,
+                    i,
+                    &endpoint.checks,
+                    request_summary,
+                    response.as_ref(),
+                )
+                .await;
+            }
+            Requests::Scenario(scenario) => {
+                run_iteration(
+                    client,
+                    conn_setting,
+                    &mut summary,
+                    stage,
+                    scenario,
+                    variables,
+                    &dispatch,
+                )
+                .await;
+            }
         }
-
-        summary.total_requests += 1;
 
         // send update stats
         // just send a batch instead
@@ -215,15 +991,166 @@ async fn connection_task(
     if queue_stats > 0 {
         stats.update(queue_stats).await;
     }
-    // notify finished
-    stats.finish().await;
 
-    Ok(summary)
+    summary
+}
+
+// A failed request is recorded like any other response,
+// it must not end the connection
+async fn send(
+    client: &impl Requester,
+    request: &RequestTemplate,
+    variables: &Variables,
+    keep_body: bool,
+    intended_at: Instant,
+    expected_interval: Option<Duration>,
+) -> (RequestSummary, Option<Response>) {
+    let now = Instant::now();
+    let (outcome, response) = match request.build(variables) {
+        Result::Ok(request) => match client.send(request, keep_body).await {
+            Result::Ok(response) => (Outcome::Status(response.status), Some(response)),
+            Err(err) => (Outcome::Error(RequestError::classify(&err)), None),
+        },
+        Err(_) => (Outcome::Error(RequestError::Invalid), None),
+    };
+    let request_summary = RequestSummary {
+        intended_at,
+        started_at: now,
+        expected_interval,
+        latency: now.elapsed(),
+        time_to_first_byte: response
+            .as_ref()
+            .map_or(Duration::ZERO, |r| r.time_to_first_byte),
+        body_bytes: response.as_ref().map_or(0, |r| r.body_bytes),
+        outcome,
+    };
+    (request_summary, response)
+}
+
+// Adds a request to the statistics of its stage and endpoint or step,
+// to the current interval and to the event log.
+// Returns whether the response passed the checks, false without a response
+async fn record(
+    conn_setting: &ConnectionSettings,
+    summary: &mut ConnectionSummary,
+    stage: usize,
+    endpoint: usize,
+    checks: &[Check],
+    request_summary: RequestSummary,
+    response: Option<&Response>,
+) -> bool {
+    let outcome = request_summary.outcome;
+    let mut checked = false;
+    if let Some(response) = response {
+        *summary
+            .protocols
+            .entry(response.version.clone())
+            .or_default() += 1;
+        checked = run_checks(
+            checks,
+            response,
+            request_summary.latency,
+            &mut summary.checks,
+        );
+        if !checked {
+            summary.failed_checks += 1;
+        }
+    }
+    if summary.outcomes.len() <= stage {
+        summary.outcomes.resize_with(stage + 1, HashMap::new);
+    }
+    summary.outcomes[stage]
+        .entry((endpoint, outcome))
+        .or_insert_with(|| OutcomeStats::new(conn_setting.precision))
+        .record(&request_summary);
+    let success = outcome.is_success(&conn_setting.success);
+    if let Some(window) = &conn_setting.window {
+        window.record(conn_setting.id, request_summary.latency, success);
+    }
+    if success {
+        summary.success_requests += 1;
+    } else {
+        summary.fail_requests += 1;
+    }
+    if let Some(events) = &conn_setting.events {
+        events
+            .send(conn_setting.id, endpoint, request_summary)
+            .await;
+    }
+    summary.total_requests += 1;
+    checked
+}
+
+// Sends the steps of the scenario in order, the first one that fails ends the iteration.
+// `variables` are those of the feeders, the steps add the values they extract
+async fn run_iteration(
+    client: &impl Requester,
+    conn_setting: &ConnectionSettings,
+    summary: &mut ConnectionSummary,
+    stage: usize,
+    scenario: &Scenario,
+    mut variables: Variables,
+    dispatch: &Dispatch,
+) {
+    let start = Instant::now();
+    let mut body_bytes = 0;
+    let mut outcome = IterationOutcome::Completed;
+    for (i, step) in scenario.steps().iter().enumerate() {
+        // Only the first step waits for the scheduler or keeps the pace of the
+        // stream, the others follow right away
+        let (sent_at, interval) = match i {
+            0 => (dispatch.intended_at, dispatch.expected_interval),
+            _ => (Instant::now(), None),
+        };
+        let (request_summary, response) = send(
+            client,
+            &step.request,
+            &variables,
+            step.needs_body(),
+            sent_at,
+            interval,
+        )
+        .await;
+        let success = request_summary.outcome.is_success(&conn_setting.success);
+        body_bytes += request_summary.body_bytes;
+        let extracted = success
+            && response
+                .as_ref()
+                .is_some_and(|response| step.extract(response, &mut variables));
+        let checked = record(
+            conn_setting,
+            summary,
+            stage,
+            i,
+            &step.checks,
+            request_summary,
+            response.as_ref(),
+        )
+        .await;
+        if !extracted || !checked {
+            outcome = IterationOutcome::Failed(i);
+            break;
+        }
+    }
+    let latency = start.elapsed();
+    summary
+        .iterations
+        .entry(outcome)
+        .or_insert_with(|| OutcomeStats::new(conn_setting.precision))
+        .record_span(
+            latency,
+            latency + start.saturating_duration_since(dispatch.intended_at),
+            dispatch.expected_interval,
+            body_bytes,
+        );
 }
 
 #[cfg(test)]
 mod tests {
+    use hyper::{Body, HeaderMap, Request};
+
     use super::*;
+    use crate::client::Response;
 
     struct MockHttpClient {
         status: Option<u16>,
@@ -237,9 +1164,20 @@ mod tests {
 
     #[async_trait]
     impl Requester for MockHttpClient {
-        async fn get(&self, _uri: Uri) -> anyhow::Result<u16> {
+        async fn send(
+            &self,
+            _request: Request<Body>,
+            _keep_body: bool,
+        ) -> anyhow::Result<Response> {
             match self.status {
-                Some(status) => Ok(status),
+                Some(status) => Ok(Response {
+                    status,
+                    version: String::from("HTTP/1.1"),
+                    time_to_first_byte: Duration::ZERO,
+                    body_bytes: 0,
+                    headers: HeaderMap::new(),
+                    body: vec![],
+                }),
                 None => Err(anyhow::Error::msg("Test")),
             }
         }
@@ -249,18 +1187,29 @@ mod tests {
 
     #[async_trait]
     impl TaskStats for MockTaskNotifier {
-        async fn update(&self, _n: u64) -> () {
-            ()
-        }
-        async fn finish(&self) -> () {
-            ()
-        }
+        async fn update(&self, _n: u64) {}
+        async fn finish(&self) {}
+    }
+
+    fn mock_workload() -> Arc<Workload> {
+        let request = RequestTemplate::get("http://localhost/").unwrap();
+        Arc::new(Workload {
+            requests: Requests::Endpoints(Mix::new(vec![Endpoint::new("abc", request)]).unwrap()),
+            feeders: vec![],
+        })
     }
 
     fn mock_conn_settings() -> ConnectionSettings {
         ConnectionSettings {
-            requests: 10,
-            target_uri: Uri::from_static("abc"),
+            id: 0,
+            pacing: Pacing::Budget(Arc::new(AtomicU64::new(10))),
+            streams: 1,
+            precision: DEFAULT_PRECISION,
+            success: StatusSet::default(),
+            timeline: Arc::new(Timeline::new(Instant::now(), vec![])),
+            window: None,
+            events: None,
+            workload: mock_workload(),
         }
     }
 
@@ -299,7 +1248,365 @@ mod tests {
             MockTaskNotifier {},
             mock_conn_settings(),
         )
-        .await;
-        assert!(result.is_err());
+        .await
+        .expect("No error");
+
+        assert_eq!(result.total_requests, 10);
+        assert_eq!(result.fail_requests, 10);
+        assert_eq!(result.outcomes[0].len(), 1);
+        assert_eq!(
+            result.outcomes[0][&(0, Outcome::Error(RequestError::Other))].requests(),
+            10
+        );
+    }
+
+    #[test]
+    fn request_error_classifies_io_errors() {
+        let refused = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert_eq!(
+            RequestError::classify(&refused),
+            RequestError::ConnectRefused
+        );
+
+        let reset = anyhow::Error::new(io::Error::from(io::ErrorKind::ConnectionReset))
+            .context("Error making request");
+        assert_eq!(
+            RequestError::classify(&reset),
+            RequestError::ConnectionReset
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_task_runs_until_deadline() {
+        let conn_settings = ConnectionSettings {
+            pacing: Pacing::Deadline(Instant::now() + Duration::from_millis(20)),
+            ..mock_conn_settings()
+        };
+        let now = Instant::now();
+        let result = connection_task(
+            MockHttpClient::with_status(Some(200)),
+            MockTaskNotifier {},
+            conn_settings,
+        )
+        .await
+        .expect("No error");
+
+        assert!(now.elapsed() >= Duration::from_millis(20));
+        assert!(result.total_requests > 0);
+    }
+
+    #[tokio::test]
+    async fn schedule_task_counts_late_when_workers_busy() {
+        let idle_workers = Arc::new(Semaphore::new(1));
+        let (queue_tx, mut queue_rx) = unbounded_channel();
+        let scheduler = tokio::spawn(schedule_task(
+            ScheduleSettings {
+                rate: 1000,
+                requests: 10,
+                deadline: None,
+                timeline: None,
+            },
+            idle_workers,
+            queue_tx,
+        ));
+
+        // The only worker takes 5ms per request, far slower than the schedule
+        let mut dispatches = vec![];
+        while let Some(dispatch) = queue_rx.recv().await {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            dispatches.push(dispatch.intended_at);
+        }
+
+        let late = scheduler.await.unwrap();
+        assert_eq!(dispatches.len(), 10);
+        assert!(late > 0);
+        // Late requests keep their place in the schedule
+        assert!(dispatches[9] - dispatches[0] < Duration::from_millis(20));
+    }
+
+    #[test]
+    fn corrected_latency_includes_waiting_time() {
+        let intended_at = Instant::now();
+        let summary = RequestSummary {
+            intended_at,
+            started_at: intended_at + Duration::from_millis(30),
+            expected_interval: None,
+            latency: Duration::from_millis(10),
+            time_to_first_byte: Duration::from_millis(5),
+            body_bytes: 0,
+            outcome: Outcome::Status(200),
+        };
+        assert_eq!(summary.corrected_latency(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn pace_is_the_average_interval_so_far() {
+        let start = Instant::now();
+        let mut pace = Pace::default();
+        assert_eq!(pace.next(start), None);
+        assert_eq!(
+            pace.next(start + Duration::from_millis(10)),
+            Some(Duration::from_millis(10))
+        );
+        // A stall slows the pace down but does not reset it
+        assert_eq!(
+            pace.next(start + Duration::from_millis(110)),
+            Some(Duration::from_millis(55))
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_tasks_share_request_budget() {
+        let budget = Arc::new(AtomicU64::new(25));
+        let conn_settings = || ConnectionSettings {
+            pacing: Pacing::Budget(budget.clone()),
+            ..mock_conn_settings()
+        };
+
+        let (first, second) = tokio::join!(
+            connection_task(
+                MockHttpClient::with_status(Some(200)),
+                MockTaskNotifier {},
+                conn_settings(),
+            ),
+            connection_task(
+                MockHttpClient::with_status(Some(200)),
+                MockTaskNotifier {},
+                conn_settings(),
+            )
+        );
+
+        let total = first.unwrap().total_requests + second.unwrap().total_requests;
+        assert_eq!(total, 25);
+        assert_eq!(budget.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn connection_task_multiplexes_streams() {
+        let conn_settings = ConnectionSettings {
+            streams: 4,
+            ..mock_conn_settings()
+        };
+        let result = connection_task(
+            MockHttpClient::with_status(Some(200)),
+            MockTaskNotifier {},
+            conn_settings,
+        )
+        .await
+        .expect("No error");
+
+        assert_eq!(result.total_requests, 10);
+        assert_eq!(result.protocols.get("HTTP/1.1"), Some(&10));
+    }
+
+    #[tokio::test]
+    async fn connection_task_records_each_endpoint() {
+        let mut conn_settings = mock_conn_settings();
+        conn_settings.pacing = Pacing::Budget(Arc::new(AtomicU64::new(1000)));
+        let request = RequestTemplate::get("http://localhost/").unwrap();
+        conn_settings.workload = Arc::new(Workload {
+            requests: Requests::Endpoints(
+                Mix::new(vec![
+                    Endpoint::new("read", request.clone()).with_weight(3),
+                    Endpoint::new("write", request),
+                ])
+                .unwrap(),
+            ),
+            feeders: vec![],
+        });
+        let mut result = BenchmarkResult::new(Uri::from_static("abc"));
+        result.endpoints = ["read", "write"]
+            .map(|name| EndpointSummary {
+                name: name.to_string(),
+                outcomes: HashMap::new(),
+            })
+            .into();
+
+        let summary = connection_task(
+            MockHttpClient::with_status(Some(200)),
+            MockTaskNotifier {},
+            conn_settings,
+        )
+        .await
+        .expect("No error");
+        result.combine_conn_summaries(vec![summary]);
+
+        let requests = |i: usize| result.endpoints[i].outcomes[&Outcome::Status(200)].requests();
+        assert_eq!(requests(0) + requests(1), 1000);
+        assert!(requests(0) > requests(1) * 2);
+        assert_eq!(result.total_requests(), 1000);
+    }
+
+    #[tokio::test]
+    async fn connection_task_stops_when_feeder_runs_out() {
+        let rows = (0..3)
+            .map(|i| Variables::from([(String::from("id"), i.to_string())]))
+            .collect();
+        let feeder = Feeder::new("ids", rows, FeedOrder::Sequential, FeedScope::Request, true);
+        let request = RequestTemplate::get("http://localhost/person/{{id}}").unwrap();
+        let mut conn_settings = mock_conn_settings();
+        conn_settings.workload = Arc::new(Workload {
+            requests: Requests::Endpoints(Mix::new(vec![Endpoint::new("abc", request)]).unwrap()),
+            feeders: vec![feeder.unwrap()],
+        });
+
+        let summary = connection_task(
+            MockHttpClient::with_status(Some(200)),
+            MockTaskNotifier {},
+            conn_settings,
+        )
+        .await
+        .expect("No error");
+
+        assert_eq!(summary.total_requests, 3);
+    }
+
+    #[tokio::test]
+    async fn connection_task_counts_success_statuses() {
+        for (statuses, successes) in [("2xx", 10), ("200,304", 0)] {
+            let mut conn_settings = mock_conn_settings();
+            conn_settings.success = statuses.parse().unwrap();
+            let result = connection_task(
+                MockHttpClient::with_status(Some(204)),
+                MockTaskNotifier {},
+                conn_settings,
+            )
+            .await
+            .expect("No error");
+
+            assert_eq!(result.success_requests, successes);
+            assert_eq!(result.fail_requests, 10 - successes);
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_task_counts_checks() {
+        let check = |condition: &str| Check::new(condition, condition.parse().unwrap());
+        let endpoint = Endpoint::new("abc", RequestTemplate::get("http://localhost/").unwrap())
+            .with_check(check("status:2xx"))
+            .with_check(check("header:etag"));
+        let mut conn_settings = mock_conn_settings();
+        conn_settings.workload = Arc::new(Workload {
+            requests: Requests::Endpoints(Mix::new(vec![endpoint]).unwrap()),
+            feeders: vec![],
+        });
+
+        let summary = connection_task(
+            MockHttpClient::with_status(Some(200)),
+            MockTaskNotifier {},
+            conn_settings,
+        )
+        .await
+        .expect("No error");
+
+        // A failed check does not make the request an error
+        assert_eq!(summary.success_requests, 10);
+        assert_eq!(summary.failed_checks, 10);
+        assert_eq!(
+            summary.checks["status:2xx"],
+            CheckCount {
+                passed: 10,
+                failed: 0
+            }
+        );
+        assert_eq!(summary.checks["header:etag"].failed, 10);
+    }
+
+    // Logs in with a token from the body, then only answers requests carrying it
+    struct MockLoginClient {}
+
+    #[async_trait]
+    impl Requester for MockLoginClient {
+        async fn send(&self, request: Request<Body>, keep_body: bool) -> anyhow::Result<Response> {
+            let (status, body) = match request.uri().path() {
+                "/login" => (200, &br#"{"token": "abc"}"#[..]),
+                _ if request
+                    .headers()
+                    .get("authorization")
+                    .is_some_and(|v| v == "Bearer abc") =>
+                {
+                    (200, &b"{}"[..])
+                }
+                _ => (401, &b""[..]),
+            };
+            Ok(Response {
+                status,
+                version: String::from("HTTP/1.1"),
+                time_to_first_byte: Duration::ZERO,
+                body_bytes: body.len() as u64,
+                headers: HeaderMap::new(),
+                body: if keep_body { body.to_vec() } else { vec![] },
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_task_runs_scenario_steps() {
+        let get = |uri: &str| RequestTemplate::get(uri).unwrap();
+        let login = Step::new("login", get("http://localhost/login"))
+            .with_extraction("token", "json:$.token".parse().unwrap());
+        let person = Step::new(
+            "person",
+            get("http://localhost/person")
+                .with_header("Authorization: Bearer {{token}}")
+                .unwrap(),
+        )
+        .with_extraction("name", "json:$.name".parse().unwrap());
+        let logout = Step::new("logout", get("http://localhost/logout/{{name}}"));
+        let mut conn_settings = mock_conn_settings();
+        conn_settings.workload = Arc::new(Workload {
+            requests: Requests::Scenario(Scenario::new(vec![login, person, logout]).unwrap()),
+            feeders: vec![],
+        });
+
+        let summary = connection_task(MockLoginClient {}, MockTaskNotifier {}, conn_settings)
+            .await
+            .expect("No error");
+
+        // Every iteration gets the person, then finds no name to log out with
+        assert_eq!(summary.total_requests, 20);
+        assert_eq!(summary.success_requests, 20);
+        assert_eq!(
+            summary.outcomes[0][&(1, Outcome::Status(200))].requests(),
+            10
+        );
+        assert!(!summary.outcomes[0].keys().any(|(step, _)| *step == 2));
+        assert_eq!(summary.iterations.len(), 1);
+        assert_eq!(
+            summary.iterations[&IterationOutcome::Failed(1)].requests(),
+            10
+        );
+    }
+
+    #[tokio::test]
+    async fn connection_task_streams_request_events() {
+        let (tx, mut rx) = channel(4);
+        let started_at = SystemTime::now();
+        let mut conn_settings = mock_conn_settings();
+        conn_settings.id = 7;
+        conn_settings.events = Some(Arc::new(EventLog {
+            tx,
+            start: Instant::now(),
+            started_at,
+        }));
+
+        // The channel holds fewer events than requests, so they must be consumed as they come
+        let task = tokio::spawn(connection_task(
+            MockHttpClient::with_status(Some(200)),
+            MockTaskNotifier {},
+            conn_settings,
+        ));
+        let mut events = vec![];
+        while let Some(event) = rx.recv().await {
+            events.push(event);
+        }
+        task.await.unwrap().unwrap();
+
+        assert_eq!(events.len(), 10);
+        assert!(events.iter().all(|e| e.connection == 7));
+        assert!(events.iter().all(|e| e.timestamp >= started_at));
+        assert!(events
+            .iter()
+            .all(|e| e.summary.outcome == Outcome::Status(200)));
     }
 }
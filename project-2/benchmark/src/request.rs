@@ -0,0 +1,147 @@
+use anyhow::Context;
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    Body, Method, Request, Uri,
+};
+
+use crate::template::{Template, Variables};
+
+/// Everything needed to build the request each connection sends. The URI, the
+/// header values and the body may contain `{{name}}` placeholders, filled in for
+/// every request from the feeders or the values extracted by earlier steps. Values
+/// in the path or query of the URI are percent-encoded.
+#[derive(Debug, Clone)]
+pub struct RequestTemplate {
+    pub method: Method,
+    pub uri: Template,
+    pub headers: Vec<(HeaderName, Template)>,
+    pub body: Template,
+}
+
+impl RequestTemplate {
+    /// Fails when the URI needs no variable and is still invalid.
+    pub fn get(uri: &str) -> anyhow::Result<Self> {
+        let template: Template = uri.parse()?;
+        if template.variables().next().is_none() {
+            let rendered = template.render(&Variables::new())?;
+            rendered
+                .parse::<Uri>()
+                .with_context(|| format!("Invalid URI {:?}", uri))?;
+        }
+        Ok(Self {
+            method: Method::GET,
+            uri: template,
+            headers: vec![],
+            body: Template::default(),
+        })
+    }
+
+    pub fn with_method(mut self, method: &str) -> anyhow::Result<Self> {
+        self.method = Method::from_bytes(method.to_uppercase().as_bytes())
+            .with_context(|| format!("Invalid method {:?}", method))?;
+        Ok(self)
+    }
+
+    /// Adds a header given as `Name: value`, a name may be added more than once.
+    pub fn with_header(mut self, header: &str) -> anyhow::Result<Self> {
+        let (name, value) = header
+            .split_once(':')
+            .with_context(|| format!("Header {:?} is not in the form 'Name: value'", header))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .with_context(|| format!("Invalid header name {:?}", name))?;
+        let template: Template = value.trim().parse()?;
+        if template.variables().next().is_none() {
+            HeaderValue::from_str(&template.render(&Variables::new())?)
+                .with_context(|| format!("Invalid value for header {}", name))?;
+        }
+        self.headers.push((name, template));
+        Ok(self)
+    }
+
+    pub fn with_body(mut self, body: &str) -> anyhow::Result<Self> {
+        self.body = body.parse()?;
+        Ok(self)
+    }
+
+    /// Names of the variables the request needs.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.uri
+            .variables()
+            .chain(self.headers.iter().flat_map(|(_, value)| value.variables()))
+            .chain(self.body.variables())
+    }
+
+    // Fails when a variable is missing or its value does not fit where it is used
+    pub(crate) fn build(&self, variables: &Variables) -> anyhow::Result<Request<Body>> {
+        let uri = self.uri.render_uri(variables)?;
+        let mut request = Request::new(Body::from(self.body.render(variables)?));
+        *request.method_mut() = self.method.clone();
+        *request.uri_mut() = uri
+            .parse()
+            .with_context(|| format!("Invalid URI {:?}", uri))?;
+        let headers = request.headers_mut();
+        for (name, value) in &self.headers {
+            let value = HeaderValue::from_str(&value.render(variables)?)
+                .with_context(|| format!("Invalid value for header {}", name))?;
+            headers.append(name.clone(), value);
+        }
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_request_from_template() {
+        let template = RequestTemplate::get("http://localhost/person/{{id}}")
+            .unwrap()
+            .with_method("post")
+            .unwrap()
+            .with_header("Content-Type: application/json")
+            .unwrap()
+            .with_header("X-Name: {{name}}")
+            .unwrap()
+            .with_body("{\"name\": \"{{name}}\"}")
+            .unwrap();
+        assert_eq!(
+            template.variables().collect::<Vec<_>>(),
+            ["id", "name", "name"]
+        );
+
+        let variables = Variables::from([
+            (String::from("id"), String::from("42")),
+            (String::from("name"), String::from("Ada")),
+        ]);
+        let request = template.build(&variables).unwrap();
+        assert_eq!(request.method(), Method::POST);
+        assert_eq!(request.uri(), "http://localhost/person/42");
+        assert_eq!(request.headers()["content-type"], "application/json");
+        assert_eq!(request.headers()["x-name"], "Ada");
+
+        let variables = Variables::from([
+            (String::from("id"), String::from("4 2")),
+            (String::from("name"), String::from("Ada")),
+        ]);
+        let request = template.build(&variables).unwrap();
+        assert_eq!(request.uri(), "http://localhost/person/4%202");
+
+        let variables = Variables::from([
+            (String::from("id"), String::from("42")),
+            (String::from("name"), String::from("Ada\nLovelace")),
+        ]);
+        assert!(template.build(&variables).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_header() {
+        let template = RequestTemplate::get("http://localhost/person").unwrap();
+        assert!(template
+            .clone()
+            .with_header("Content-Type application/json")
+            .is_err());
+        assert!(template.with_header("X-Name: a\nb").is_err());
+        assert!(RequestTemplate::get("http://local host/").is_err());
+    }
+}
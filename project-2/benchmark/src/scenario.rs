@@ -0,0 +1,188 @@
+use serde_json::Value;
+
+use crate::{
+    client::Response,
+    template::{is_generator, Variables},
+    Check, Extraction, Extractor, RequestTemplate,
+};
+
+/// Requests a virtual user sends one after the other, every iteration starting
+/// over from the first step. Values extracted from the response of a step can be
+/// used as `{{name}}` in the URI, headers and body of the steps after it.
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    steps: Vec<Step>,
+}
+
+/// One request of a scenario.
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// Name the statistics of the step are reported under
+    pub name: String,
+    pub request: RequestTemplate,
+    pub extractions: Vec<Extraction>,
+    /// Run on every response of the step, a failed check ends the iteration
+    pub checks: Vec<Check>,
+}
+
+/// How an iteration of a scenario ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IterationOutcome {
+    /// Every step got a successful response
+    Completed,
+    /// Stopped at the step of this index, because it got an unsuccessful response,
+    /// a check failed or a value could not be extracted from it. The steps after it were not sent.
+    Failed(usize),
+}
+
+impl Scenario {
+    pub fn new(steps: Vec<Step>) -> anyhow::Result<Self> {
+        anyhow::ensure!(!steps.is_empty(), "A scenario needs at least one step");
+        for step in &steps {
+            if let Some(e) = step.extractions.iter().find(|e| is_generator(&e.variable)) {
+                anyhow::bail!(
+                    "Step {:?} extracts {:?}, which is the name of a generated value",
+                    step.name,
+                    e.variable
+                );
+            }
+        }
+        Ok(Self { steps })
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    // Fails when a step uses a variable that is neither provided up front
+    // nor extracted by a step before it
+    pub(crate) fn check(&self, provided: &[&str]) -> anyhow::Result<()> {
+        let mut known = provided.to_vec();
+        for step in &self.steps {
+            for variable in step.request.variables() {
+                anyhow::ensure!(
+                    known.contains(&variable),
+                    "Step {:?} uses {{{{{}}}}}, which no feeder or step before it provides",
+                    step.name,
+                    variable
+                );
+            }
+            known.extend(step.extractions.iter().map(|e| e.variable.as_str()));
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    pub fn new(name: impl Into<String>, request: RequestTemplate) -> Self {
+        Self {
+            name: name.into(),
+            request,
+            extractions: vec![],
+            checks: vec![],
+        }
+    }
+
+    pub fn with_check(mut self, check: Check) -> Self {
+        self.checks.push(check);
+        self
+    }
+
+    pub fn with_extraction(mut self, variable: impl Into<String>, extractor: Extractor) -> Self {
+        self.extractions.push(Extraction {
+            variable: variable.into(),
+            extractor,
+        });
+        self
+    }
+
+    pub(crate) fn needs_body(&self) -> bool {
+        self.extractions.iter().any(|e| e.extractor.needs_body())
+            || self.checks.iter().any(|c| c.condition.needs_body())
+    }
+
+    // Sets the variables of the step from its response,
+    // returns false when one of them is not found
+    pub(crate) fn extract(&self, response: &Response, variables: &mut Variables) -> bool {
+        let json = self
+            .extractions
+            .iter()
+            .any(|e| matches!(e.extractor, Extractor::JsonPath(_)))
+            .then(|| serde_json::from_slice::<Value>(&response.body).ok())
+            .flatten();
+        for extraction in &self.extractions {
+            match extraction
+                .extractor
+                .extract(&response.headers, &response.body, json.as_ref())
+            {
+                Some(value) => {
+                    variables.insert(extraction.variable.clone(), value);
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyper::HeaderMap;
+
+    use super::*;
+
+    fn step(name: &str, uri: &str) -> Step {
+        Step::new(name, RequestTemplate::get(uri).unwrap())
+    }
+
+    #[test]
+    fn steps_only_use_variables_provided_before() {
+        let login = step("login", "http://localhost/login/{{user}}")
+            .with_extraction("token", "json:$.token".parse().unwrap());
+        let mut person = step("person", "http://localhost/person/{{id}}");
+        person.request = person
+            .request
+            .with_header("Authorization: Bearer {{token}}")
+            .unwrap();
+
+        let scenario = Scenario::new(vec![login.clone(), person.clone()]).unwrap();
+        let err = scenario.check(&["user"]).unwrap_err();
+        assert!(err.to_string().contains("{{id}}"), "{}", err);
+        assert!(scenario.check(&["user", "id"]).is_ok());
+
+        let login = login.with_extraction("id", "json:$.id".parse().unwrap());
+        let scenario = Scenario::new(vec![login.clone(), person.clone()]).unwrap();
+        assert!(scenario.check(&["user"]).is_ok());
+        let reversed = Scenario::new(vec![person, login]).unwrap();
+        assert!(reversed.check(&["user"]).is_err());
+        assert!(Scenario::new(vec![]).is_err());
+        let seq = step("seq", "http://localhost/")
+            .with_extraction("seq", "header:X-Seq".parse().unwrap());
+        assert!(Scenario::new(vec![seq]).is_err());
+    }
+
+    #[test]
+    fn extracted_values_are_encoded_in_later_uris() {
+        let search = step("search", "http://localhost/search")
+            .with_extraction("title", "json:$.title".parse().unwrap());
+        let response = Response {
+            status: 200,
+            version: String::from("HTTP/1.1"),
+            time_to_first_byte: Duration::ZERO,
+            body_bytes: 0,
+            headers: HeaderMap::new(),
+            body: br#"{"title": "Red shoes & more?"}"#.to_vec(),
+        };
+        let mut variables = Variables::new();
+        assert!(search.extract(&response, &mut variables));
+
+        let open = step("open", "http://localhost/?t={{title}}");
+        let request = open.request.build(&variables).unwrap();
+        assert_eq!(
+            request.uri(),
+            "http://localhost/?t=Red%20shoes%20%26%20more%3F"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use hdrhistogram::Histogram;
+
+/// What happened during one interval of the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// Time from the start of the run to the end of the interval
+    pub elapsed: Duration,
+    /// Length of the interval, the last one of a run is usually shorter
+    pub interval: Duration,
+    /// The last interval of a run, cut short by its end. Its rate is over
+    /// little time and mostly made of requests that were still in flight.
+    pub partial: bool,
+    /// Requests completed during the interval
+    pub requests: u64,
+    /// Completed requests that were not successful
+    pub errors: u64,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+}
+
+impl Snapshot {
+    pub fn rps(&self) -> f64 {
+        self.requests as f64 / self.interval.as_secs_f64()
+    }
+}
+
+// Requests completed since the last snapshot
+pub(crate) struct Window {
+    start: Instant,
+    precision: u8,
+    // One per connection so recording a request never waits for another
+    // connection, the ticker merges them when it closes an interval
+    connections: Vec<Mutex<Recorded>>,
+    // Start of the current interval
+    since: Mutex<Instant>,
+}
+
+struct Recorded {
+    latency: Histogram<u64>,
+    errors: u64,
+}
+
+struct WindowStats {
+    since: Instant,
+    latency: Histogram<u64>,
+    errors: u64,
+}
+
+impl WindowStats {
+    fn snapshot(&self, start: Instant, now: Instant, partial: bool) -> Snapshot {
+        let percentile = |q: f64| Duration::from_micros(self.latency.value_at_quantile(q));
+        Snapshot {
+            elapsed: now - start,
+            interval: now - self.since,
+            partial,
+            requests: self.latency.len(),
+            errors: self.errors,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+        }
+    }
+}
+
+impl Window {
+    pub(crate) fn new(start: Instant, precision: u8, connections: u16) -> Self {
+        Self {
+            start,
+            precision,
+            connections: (0..connections)
+                .map(|_| {
+                    Mutex::new(Recorded {
+                        latency: histogram(precision),
+                        errors: 0,
+                    })
+                })
+                .collect(),
+            since: Mutex::new(start),
+        }
+    }
+
+    pub(crate) fn record(&self, connection: u16, latency: Duration, success: bool) {
+        let mut recorded = self.connections[usize::from(connection)].lock().unwrap();
+        recorded
+            .latency
+            .record(latency.as_micros() as u64)
+            .expect("Resizable histogram");
+        if !success {
+            recorded.errors += 1;
+        }
+    }
+
+    // Moves what every connection recorded since the last call into one interval
+    fn drain(&self, since: Instant) -> WindowStats {
+        let mut stats = WindowStats {
+            since,
+            latency: histogram(self.precision),
+            errors: 0,
+        };
+        for connection in &self.connections {
+            let mut recorded = connection.lock().unwrap();
+            stats
+                .latency
+                .add(&recorded.latency)
+                .expect("Resizable histogram");
+            stats.errors += recorded.errors;
+            recorded.latency.reset();
+            recorded.errors = 0;
+        }
+        stats
+    }
+
+    // Closes the interval and starts the next one
+    pub(crate) fn take(&self) -> Snapshot {
+        let now = Instant::now();
+        let since = std::mem::replace(&mut *self.since.lock().unwrap(), now);
+        self.drain(since).snapshot(self.start, now, false)
+    }
+
+    // Closes the last interval of the run, `None` when no request completed in it.
+    // Snapshots before it were already reported, so a tail shorter than `period`
+    // is its own snapshot marked as partial rather than joining the previous one.
+    pub(crate) fn finish(&self, period: Duration) -> Option<Snapshot> {
+        let now = Instant::now();
+        let tail = self.drain(*self.since.lock().unwrap());
+        if tail.latency.is_empty() {
+            return None;
+        }
+        Some(tail.snapshot(self.start, now, now - tail.since < period))
+    }
+}
+
+fn histogram(precision: u8) -> Histogram<u64> {
+    Histogram::new(precision).expect("Precision is between 1 and 5")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_only_cover_their_interval() {
+        // The requests of both connections make one snapshot
+        let window = Window::new(Instant::now(), 3, 2);
+        for i in 1..=100 {
+            window.record(i as u16 % 2, Duration::from_millis(i), i % 10 != 0);
+        }
+
+        let first = window.take();
+        assert_eq!(first.requests, 100);
+        assert_eq!(first.errors, 10);
+        assert!(first.p50 >= Duration::from_millis(49) && first.p50 <= Duration::from_millis(51));
+        assert!(first.p99 >= Duration::from_millis(98));
+
+        window.record(0, Duration::from_millis(5), true);
+        let second = window.take();
+        assert_eq!(second.requests, 1);
+        assert_eq!(second.errors, 0);
+        assert!(second.elapsed > first.elapsed);
+    }
+
+    #[test]
+    fn short_tail_is_a_partial_snapshot() {
+        let window = Window::new(Instant::now(), 3, 1);
+        window.record(0, Duration::from_millis(10), true);
+        let first = window.take();
+        assert!(!first.partial);
+
+        window.record(0, Duration::from_millis(20), false);
+        let tail = window.finish(Duration::from_secs(1)).unwrap();
+        assert!(tail.partial);
+        assert_eq!(tail.requests, 1);
+        assert_eq!(tail.errors, 1);
+
+        // A tail as long as an interval is a whole one, no tail is no snapshot
+        let window = Window::new(Instant::now(), 3, 1);
+        window.record(0, Duration::from_millis(30), true);
+        assert!(!window.finish(Duration::ZERO).unwrap().partial);
+        assert_eq!(window.finish(Duration::ZERO), None);
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+/// One step of a load profile: the target moves linearly from `from` to `to`
+/// over `duration`. The target is a number of connections or a rate, see `StageTarget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stage {
+    pub duration: Duration,
+    pub from: u32,
+    pub to: u32,
+}
+
+/// What the values of the stages control.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StageTarget {
+    /// Number of active connections, each sending its next request as soon
+    /// as the previous one returns
+    #[default]
+    Connections,
+    /// Requests per second dispatched to the pool of connections
+    Rate,
+}
+
+impl Stage {
+    fn target_at(&self, offset: Duration) -> f64 {
+        let progress = offset.as_secs_f64() / self.duration.as_secs_f64();
+        self.from as f64 + (self.to as f64 - self.from as f64) * progress.min(1_f64)
+    }
+
+    /// The highest target the stage reaches
+    pub fn peak(&self) -> u32 {
+        self.from.max(self.to)
+    }
+}
+
+/// Parses `30s:0->500` for a ramp and `5m:500` for a plateau.
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (duration, target) = s
+            .split_once(':')
+            .with_context(|| format!("Stage {:?} is not in the form 'duration:from->to'", s))?;
+        let duration = humantime::parse_duration(duration.trim())
+            .with_context(|| format!("Invalid duration in stage {:?}", s))?;
+        anyhow::ensure!(!duration.is_zero(), "Stage {:?} has no duration", s);
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .with_context(|| format!("Invalid target in stage {:?}", s))
+        };
+        let (from, to) = match target.split_once("->") {
+            Some((from, to)) => (parse(from)?, parse(to)?),
+            None => (parse(target)?, parse(target)?),
+        };
+        Ok(Stage { duration, from, to })
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let duration = humantime::format_duration(self.duration);
+        if self.from == self.to {
+            write!(f, "{}:{}", duration, self.from)
+        } else {
+            write!(f, "{}:{}->{}", duration, self.from, self.to)
+        }
+    }
+}
+
+// The stages laid out one after the other from the start of the run
+pub(crate) struct Timeline {
+    start: Instant,
+    stages: Vec<Stage>,
+}
+
+impl Timeline {
+    pub(crate) fn new(start: Instant, stages: Vec<Stage>) -> Self {
+        Self { start, stages }
+    }
+
+    pub(crate) fn start(&self) -> Instant {
+        self.start
+    }
+
+    pub(crate) fn total(&self) -> Duration {
+        self.stages.iter().map(|s| s.duration).sum()
+    }
+
+    // Index of the stage a request intended at `at` belongs to,
+    // requests still in flight after the last stage count towards it
+    pub(crate) fn stage_index(&self, at: Instant) -> usize {
+        let mut elapsed = at.saturating_duration_since(self.start);
+        for (i, stage) in self.stages.iter().enumerate() {
+            if elapsed < stage.duration {
+                return i;
+            }
+            elapsed -= stage.duration;
+        }
+        self.stages.len().saturating_sub(1)
+    }
+
+    // The stage running at `elapsed` and the offset into it
+    fn locate(&self, mut elapsed: Duration) -> Option<(usize, Duration)> {
+        for (i, stage) in self.stages.iter().enumerate() {
+            if elapsed < stage.duration {
+                return Some((i, elapsed));
+            }
+            elapsed -= stage.duration;
+        }
+        None
+    }
+
+    /// Earliest time since the start, not before `elapsed`, at which the target
+    /// is at least `level`. `None` when that never happens before the end.
+    pub(crate) fn reaches(&self, level: f64, elapsed: Duration) -> Option<Duration> {
+        let (first, mut offset) = self.locate(elapsed)?;
+        let mut stage_start = elapsed - offset;
+        for stage in &self.stages[first..] {
+            if stage.target_at(offset) >= level {
+                return Some(stage_start + offset);
+            }
+            if stage.to as f64 >= level {
+                // Ramping up, the target crosses the level within this stage
+                let progress = (level - stage.from as f64) / (stage.to as f64 - stage.from as f64);
+                return Some(stage_start + stage.duration.mul_f64(progress).max(offset));
+            }
+            stage_start += stage.duration;
+            offset = Duration::ZERO;
+        }
+        None
+    }
+
+    /// When the request following one sent at `elapsed` is due, with the stage
+    /// values as rates. `None` when the profile ends first.
+    pub(crate) fn next_arrival(&self, elapsed: Duration) -> Option<Duration> {
+        let (first, mut offset) = self.locate(elapsed)?;
+        let mut stage_start = elapsed - offset;
+        // Requests still owed, the integral of the rate until the next arrival is one
+        let mut owed = 1_f64;
+        for stage in &self.stages[first..] {
+            let length = stage.duration.as_secs_f64();
+            let from = stage.from as f64;
+            let slope = (stage.to as f64 - from) / length;
+            let t0 = offset.as_secs_f64();
+            let area = from * (length - t0) + slope / 2_f64 * (length * length - t0 * t0);
+            if area >= owed {
+                // Solve from * t + slope / 2 * t^2 = from * t0 + slope / 2 * t0^2 + owed
+                let c = from * t0 + slope / 2_f64 * t0 * t0 + owed;
+                let t = if slope.abs() < f64::EPSILON {
+                    c / from
+                } else {
+                    (-from + (from * from + 2_f64 * slope * c).max(0_f64).sqrt()) / slope
+                };
+                return Some(stage_start + Duration::from_secs_f64(t.clamp(t0, length)));
+            }
+            owed -= area;
+            stage_start += stage.duration;
+            offset = Duration::ZERO;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timeline(stages: &[&str]) -> Timeline {
+        Timeline::new(
+            Instant::now(),
+            stages.iter().map(|s| s.parse().unwrap()).collect(),
+        )
+    }
+
+    #[test]
+    fn parses_ramps_and_plateaus() {
+        let ramp: Stage = "30s:0->500".parse().unwrap();
+        assert_eq!(ramp.duration, Duration::from_secs(30));
+        assert_eq!((ramp.from, ramp.to), (0, 500));
+
+        let plateau: Stage = "5m:500".parse().unwrap();
+        assert_eq!((plateau.from, plateau.to), (500, 500));
+        assert_eq!(plateau.to_string(), "5m:500");
+
+        assert!("30s".parse::<Stage>().is_err());
+        assert!("0s:10".parse::<Stage>().is_err());
+        assert!("30s:a->b".parse::<Stage>().is_err());
+    }
+
+    #[test]
+    fn connections_join_along_the_ramp() {
+        let timeline = timeline(&["10s:0->10", "10s:10", "10s:10->0"]);
+        assert_eq!(
+            timeline.reaches(5_f64, Duration::ZERO),
+            Some(Duration::from_secs(5))
+        );
+        // Already active during the plateau
+        assert_eq!(
+            timeline.reaches(10_f64, Duration::from_secs(12)),
+            Some(Duration::from_secs(12))
+        );
+        // Never active again once the ramp down passed it
+        assert_eq!(timeline.reaches(5_f64, Duration::from_secs(26)), None);
+    }
+
+    #[test]
+    fn arrivals_follow_the_rate() {
+        let timeline = timeline(&["1s:100", "2s:0->100"]);
+        let mut arrivals = 0;
+        let mut elapsed = Duration::ZERO;
+        while let Some(next) = timeline.next_arrival(elapsed) {
+            arrivals += 1;
+            elapsed = next;
+        }
+        // 100 requests in the plateau and 100 along the ramp
+        assert!((199..=201).contains(&arrivals), "{}", arrivals);
+        assert_eq!(
+            timeline.stage_index(timeline.start + Duration::from_secs(2)),
+            1
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+
+/// Status codes given as a list like `200,201` or classes like `2xx,304`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusSet {
+    ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl StatusSet {
+    pub fn contains(&self, status: u16) -> bool {
+        self.ranges.iter().any(|range| range.contains(&status))
+    }
+}
+
+/// Every 2xx status.
+impl Default for StatusSet {
+    fn default() -> Self {
+        Self {
+            ranges: vec![200..=299],
+        }
+    }
+}
+
+impl FromStr for StatusSet {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s
+            .split(',')
+            .map(|status| parse_status(status.trim()))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { ranges })
+    }
+}
+
+impl fmt::Display for StatusSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, range) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            if range.start() == range.end() {
+                write!(f, "{}", range.start())?;
+            } else {
+                write!(f, "{}xx", range.start() / 100)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A status like 200, or a class like 2xx
+fn parse_status(s: &str) -> anyhow::Result<RangeInclusive<u16>> {
+    let invalid = || anyhow::anyhow!("Invalid status {:?}, expected e.g. 200 or 2xx", s);
+    match s.to_ascii_lowercase().strip_suffix("xx") {
+        Some(class) => {
+            let class: u16 = class.parse().map_err(|_| invalid())?;
+            anyhow::ensure!((1..=5).contains(&class), invalid());
+            Ok(class * 100..=class * 100 + 99)
+        }
+        None => {
+            let status: u16 = s.parse().map_err(|_| invalid())?;
+            anyhow::ensure!((100..=599).contains(&status), invalid());
+            Ok(status..=status)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_statuses_and_classes() {
+        let statuses: StatusSet = "2xx, 304".parse().unwrap();
+        assert!(statuses.contains(204));
+        assert!(statuses.contains(304));
+        assert!(!statuses.contains(404));
+        assert_eq!(statuses.to_string(), "2xx,304");
+        assert!(StatusSet::default().contains(201));
+
+        assert!("6xx".parse::<StatusSet>().is_err());
+        assert!("200,abc".parse::<StatusSet>().is_err());
+        assert!("".parse::<StatusSet>().is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Values a template can refer to by name.
+pub type Variables = HashMap<String, String>;
+
+/// Text with `{{name}}` placeholders, parsed once and filled in for every request.
+/// Besides variables, a placeholder can be a value generated every time it is filled in:
+/// `{{uuid}}`, `{{random_int(1,1000)}}`, `{{random_string(16)}}`, `{{seq}}` (a number
+/// going up by one every time) and `{{now_ms}}` (milliseconds since the Unix epoch).
+/// `{{env.NAME}}` is the environment variable, read once when the template is parsed.
+/// Feeders and extractions cannot set a variable named like a generator, see `is_generator`.
+/// `{{{{` is a literal `{{`, for text like a Mustache payload that must not be filled in.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Variable(String),
+    Generator(Generator),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Generator {
+    Uuid,
+    RandomInt(i64, i64),
+    RandomString(usize),
+    Seq,
+    NowMs,
+}
+
+// Shared by every template, so that no two requests get the same number
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+impl Generator {
+    // `None` when the placeholder is a variable
+    fn parse(placeholder: &str) -> anyhow::Result<Option<Self>> {
+        let invalid = || anyhow::anyhow!("Invalid arguments in {{{{{}}}}}", placeholder);
+        let arguments = |name: &str| {
+            placeholder
+                .strip_prefix(name)
+                .and_then(|rest| rest.trim().strip_prefix('('))
+                .and_then(|rest| rest.strip_suffix(')'))
+                .map(|arguments| arguments.split(',').map(str::trim).collect::<Vec<_>>())
+        };
+        Ok(Some(match placeholder {
+            "uuid" => Generator::Uuid,
+            "seq" => Generator::Seq,
+            "now_ms" => Generator::NowMs,
+            _ => {
+                if let Some(arguments) = arguments("random_int") {
+                    let [min, max] = arguments[..] else {
+                        return Err(invalid());
+                    };
+                    let min: i64 = min.parse().map_err(|_| invalid())?;
+                    let max: i64 = max.parse().map_err(|_| invalid())?;
+                    anyhow::ensure!(min <= max, "{{{{{}}}}} has min above max", placeholder);
+                    Generator::RandomInt(min, max)
+                } else if let Some(arguments) = arguments("random_string") {
+                    let [length] = arguments[..] else {
+                        return Err(invalid());
+                    };
+                    Generator::RandomString(length.parse().map_err(|_| invalid())?)
+                } else {
+                    return Ok(None);
+                }
+            }
+        }))
+    }
+
+    fn generate(&self, rng: &mut impl Rng, rendered: &mut String) {
+        match self {
+            Generator::Uuid => {
+                // Version 4: random but for the version and variant bits
+                let mut bytes: [u8; 16] = rng.gen();
+                bytes[6] = (bytes[6] & 0x0f) | 0x40;
+                bytes[8] = (bytes[8] & 0x3f) | 0x80;
+                rendered.reserve(36);
+                for (i, byte) in bytes.iter().enumerate() {
+                    if [4, 6, 8, 10].contains(&i) {
+                        rendered.push('-');
+                    }
+                    rendered.push(HEX_DIGITS[usize::from(byte >> 4)].into());
+                    rendered.push(HEX_DIGITS[usize::from(byte & 0x0f)].into());
+                }
+            }
+            Generator::RandomInt(min, max) => {
+                rendered.push_str(&rng.gen_range(*min..=*max).to_string())
+            }
+            Generator::RandomString(length) => {
+                rendered.extend(rng.sample_iter(Alphanumeric).take(*length).map(char::from))
+            }
+            Generator::Seq => rendered.push_str(&SEQ.fetch_add(1, Ordering::Relaxed).to_string()),
+            Generator::NowMs => {
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default();
+                rendered.push_str(&now.as_millis().to_string())
+            }
+        }
+    }
+}
+
+// Every byte but the unreserved characters of RFC 3986 becomes %XX
+fn percent_encode(value: &str, rendered: &mut String) {
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            rendered.push(byte.into());
+        } else {
+            rendered.push('%');
+            rendered.push(
+                HEX_DIGITS[usize::from(byte >> 4)]
+                    .to_ascii_uppercase()
+                    .into(),
+            );
+            rendered.push(
+                HEX_DIGITS[usize::from(byte & 0x0f)]
+                    .to_ascii_uppercase()
+                    .into(),
+            );
+        }
+    }
+}
+
+/// Whether `{{name}}` is a generated value rather than a variable.
+pub(crate) fn is_generator(name: &str) -> bool {
+    !matches!(Generator::parse(name), Ok(None))
+}
+
+impl Template {
+    /// Names of the variables the template refers to, in order.
+    pub fn variables(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Variable(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Whether it renders the same every time, without variables nor generated values.
+    pub fn is_constant(&self) -> bool {
+        self.parts.iter().all(|part| matches!(part, Part::Text(_)))
+    }
+
+    // Fails when a variable has no value
+    pub(crate) fn render(&self, variables: &Variables) -> anyhow::Result<String> {
+        self.render_with(variables, false)
+    }
+
+    // Like `render`, but values placed in the path or query of the URI are
+    // percent-encoded, so that a value like "red shoes" still makes a valid URI.
+    // Values before the path, like `{{host}}` or a whole `{{url}}`, are kept as they are.
+    pub(crate) fn render_uri(&self, variables: &Variables) -> anyhow::Result<String> {
+        self.render_with(variables, true)
+    }
+
+    fn render_with(&self, variables: &Variables, uri: bool) -> anyhow::Result<String> {
+        let mut rendered = String::new();
+        let mut rng = rand::thread_rng();
+        let mut in_path = false;
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => {
+                    if uri && !in_path {
+                        let authority = text.find("://").map_or(text.as_str(), |i| &text[i + 3..]);
+                        in_path = authority.contains(['/', '?']);
+                    }
+                    rendered.push_str(text)
+                }
+                Part::Variable(name) => {
+                    let value = variables
+                        .get(name)
+                        .with_context(|| format!("Variable {:?} is not set", name))?;
+                    if in_path {
+                        percent_encode(value, &mut rendered);
+                    } else {
+                        rendered.push_str(value);
+                    }
+                }
+                Part::Generator(generator) => generator.generate(&mut rng, &mut rendered),
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        // Text since the last placeholder, escapes included
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            text.push_str(&rest[..start]);
+            if let Some(after) = rest[start..].strip_prefix("{{{{") {
+                text.push_str("{{");
+                rest = after;
+                continue;
+            }
+            let end = rest[start..]
+                .find("}}")
+                .with_context(|| format!("Unclosed {{{{ in {:?}", s))?;
+            let name = rest[start + 2..start + end].trim();
+            anyhow::ensure!(!name.is_empty(), "Empty {{{{}}}} in {:?}", s);
+            let part = if let Some(variable) = name.strip_prefix("env.") {
+                Part::Text(std::env::var(variable).with_context(|| {
+                    format!("Environment variable {} of {:?} is not set", variable, s)
+                })?)
+            } else {
+                match Generator::parse(name)? {
+                    Some(generator) => Part::Generator(generator),
+                    None => Part::Variable(name.to_string()),
+                }
+            };
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(part);
+            rest = &rest[start + end + 2..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fills_in_variables() {
+        let template: Template = "/person/{{id}}?token={{ token }}".parse().unwrap();
+        assert_eq!(template.variables().collect::<Vec<_>>(), ["id", "token"]);
+
+        let variables = Variables::from([
+            (String::from("id"), String::from("42")),
+            (String::from("token"), String::from("abc")),
+        ]);
+        assert_eq!(template.render(&variables).unwrap(), "/person/42?token=abc");
+        assert!(template.render(&Variables::new()).is_err());
+
+        assert!("/person/{{id".parse::<Template>().is_err());
+        assert!("{{ }}".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn encodes_values_in_the_path_and_query() {
+        let variables = Variables::from([
+            (String::from("host"), String::from("localhost:8080")),
+            (String::from("q"), String::from("red shoes&size=42/é")),
+        ]);
+        let template: Template = "http://{{host}}/search/{{q}}?q={{q}}".parse().unwrap();
+        assert_eq!(
+            template.render_uri(&variables).unwrap(),
+            "http://localhost:8080/search/red%20shoes%26size%3D42%2F%C3%A9?q=red%20shoes%26size%3D42%2F%C3%A9"
+        );
+        // Only in URIs
+        let body: Template = "{{q}}".parse().unwrap();
+        assert_eq!(body.render(&variables).unwrap(), "red shoes&size=42/é");
+        let whole: Template = "{{q}}".parse().unwrap();
+        assert_eq!(whole.render_uri(&variables).unwrap(), "red shoes&size=42/é");
+    }
+
+    #[test]
+    fn escapes_braces() {
+        let template: Template = r#"{"greeting": "{{{{name}}", "id": {{id}}}"#.parse().unwrap();
+        assert_eq!(template.variables().collect::<Vec<_>>(), ["id"]);
+        let variables = Variables::from([(String::from("id"), String::from("42"))]);
+        assert_eq!(
+            template.render(&variables).unwrap(),
+            r#"{"greeting": "{{name}}", "id": 42}"#
+        );
+
+        let literal: Template = "{{{{uuid}} {{{{".parse().unwrap();
+        assert!(literal.is_constant());
+        assert_eq!(literal.render(&Variables::new()).unwrap(), "{{uuid}} {{");
+    }
+
+    #[test]
+    fn generates_values() {
+        let template: Template = "{{uuid}} {{ random_int(5, 7) }} {{random_string(16)}}"
+            .parse()
+            .unwrap();
+        assert_eq!(template.variables().count(), 0);
+        assert!(!template.is_constant());
+        let rendered = template.render(&Variables::new()).unwrap();
+        let values: Vec<&str> = rendered.split(' ').collect();
+        assert_eq!(values[0].len(), 36);
+        assert_eq!(&values[0][14..15], "4");
+        assert!((5..=7).contains(&values[1].parse::<i64>().unwrap()));
+        assert_eq!(values[2].len(), 16);
+        assert!(values[2].chars().all(|c| c.is_ascii_alphanumeric()));
+
+        let seq: Template = "{{seq}}".parse().unwrap();
+        let first: u64 = seq.render(&Variables::new()).unwrap().parse().unwrap();
+        let second: u64 = seq.render(&Variables::new()).unwrap().parse().unwrap();
+        assert!(second > first);
+
+        std::env::set_var("TEMPLATE_TEST_KEY", "secret");
+        let env: Template = "Bearer {{env.TEMPLATE_TEST_KEY}}".parse().unwrap();
+        assert!(env.is_constant());
+        assert_eq!(env.render(&Variables::new()).unwrap(), "Bearer secret");
+
+        assert!("{{env.TEMPLATE_TEST_MISSING}}".parse::<Template>().is_err());
+        assert!("{{random_int(7, 5)}}".parse::<Template>().is_err());
+        assert!("{{random_int(1)}}".parse::<Template>().is_err());
+        assert!("{{random_string(x)}}".parse::<Template>().is_err());
+        assert!(is_generator("uuid"));
+        assert!(is_generator("random_int(1, 2)"));
+        assert!(!is_generator("random_int"));
+    }
+}
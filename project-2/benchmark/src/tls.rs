@@ -0,0 +1,371 @@
+use std::{
+    fs::File,
+    future::Future,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context as _};
+use hyper::{
+    client::{
+        connect::{Connected, Connection},
+        HttpConnector,
+    },
+    http::uri::Scheme,
+    service::Service,
+    Uri,
+};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::client::TlsStream;
+
+/// How `https://` targets are verified and how the client authenticates.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// PEM bundle of extra certificate authorities to trust
+    pub ca_cert: Option<PathBuf>,
+    /// PEM certificate chain and private key for mutual TLS
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Skip verification of the server certificate
+    pub insecure: bool,
+}
+
+impl TlsSettings {
+    /// Fails when a certificate or key cannot be loaded, so that a run can be
+    /// refused up front instead of failing once it started.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        client_config(self).map(|_| ())
+    }
+}
+
+pub(crate) fn client_config(settings: &TlsSettings) -> anyhow::Result<ClientConfig> {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+    if let Some(path) = &settings.ca_cert {
+        for cert in read_certs(path)? {
+            roots
+                .add(&cert)
+                .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+        }
+    }
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let mut config = match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+            .context("Invalid client certificate")?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => bail!("A client certificate needs both a certificate and a key"),
+    };
+
+    if settings.insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NoVerification));
+    }
+    Ok(config)
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("Cannot read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificate found in {}", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("Cannot open {}", path.display()))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("Cannot read private key from {}", path.display()))?;
+    items
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("No private key found in {}", path.display()))
+}
+
+struct NoVerification;
+
+impl ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Connects plain TCP for `http://` and TLS for `https://` targets,
+/// recording how long every TLS handshake took.
+#[derive(Clone)]
+pub(crate) struct TlsConnector {
+    http: HttpConnector,
+    tls: tokio_rustls::TlsConnector,
+    http2: bool,
+    handshakes: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl TlsConnector {
+    pub(crate) fn new(
+        http: HttpConnector,
+        config: Arc<ClientConfig>,
+        http2: bool,
+        handshakes: Arc<Mutex<Vec<Duration>>>,
+    ) -> Self {
+        Self {
+            http,
+            tls: tokio_rustls::TlsConnector::from(config),
+            http2,
+            handshakes,
+        }
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+impl Service<Uri> for TlsConnector {
+    type Response = MaybeTlsStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<MaybeTlsStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.http.call(uri.clone());
+        let tls = self.tls.clone();
+        let http2 = self.http2;
+        let handshakes = self.handshakes.clone();
+        Box::pin(async move {
+            let tcp = connecting.await?;
+            if uri.scheme() != Some(&Scheme::HTTPS) {
+                return Ok(MaybeTlsStream::Plain(tcp));
+            }
+
+            let host = uri.host().unwrap_or_default();
+            let server_name = ServerName::try_from(host.trim_matches(|c| c == '[' || c == ']'))?;
+            let start = Instant::now();
+            let stream = tls.connect(server_name, tcp).await?;
+            handshakes.lock().unwrap().push(start.elapsed());
+            // Fail clearly instead of sending HTTP/2 frames to an HTTP/1 server
+            if http2 && stream.get_ref().1.alpn_protocol() != Some(b"h2") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "server did not negotiate h2 with ALPN",
+                )
+                .into());
+            }
+            Ok(MaybeTlsStream::Tls(Box::new(stream)))
+        })
+    }
+}
+
+pub(crate) enum MaybeTlsStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Connection for MaybeTlsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            MaybeTlsStream::Plain(tcp) => tcp.connected(),
+            MaybeTlsStream::Tls(tls) => {
+                let (tcp, session) = tls.get_ref();
+                if session.alpn_protocol() == Some(b"h2") {
+                    tcp.connected().negotiated_h2()
+                } else {
+                    tcp.connected()
+                }
+            }
+        }
+    }
+}
+
+impl AsyncRead for MaybeTlsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            MaybeTlsStream::Tls(tls) => Pin::new(tls).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for MaybeTlsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            MaybeTlsStream::Tls(tls) => Pin::new(tls).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(tcp) => Pin::new(tcp).poll_flush(cx),
+            MaybeTlsStream::Tls(tls) => Pin::new(tls).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            MaybeTlsStream::Plain(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            MaybeTlsStream::Tls(tls) => Pin::new(tls).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        build_uri,
+        client::{ClientSettings, HttpClient, Requester},
+        RequestError,
+    };
+    use hyper::{server::conn::Http, service::service_fn, Body, Response};
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+    use tokio::net::TcpListener;
+
+    // Serves HTTPS on localhost with a freshly generated self-signed certificate,
+    // returns the target and the certificate in PEM, which is deleted when dropped
+    async fn self_signed_server() -> (Uri, NamedTempFile) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(cert.serialize_der().unwrap())],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(tls) = acceptor.accept(tcp).await {
+                        let service = service_fn(|_| async {
+                            Result::<_, hyper::Error>::Ok(Response::new(Body::from("secure")))
+                        });
+                        let _ = Http::new().serve_connection(tls, service).await;
+                    }
+                });
+            }
+        });
+
+        let mut ca = NamedTempFile::new().unwrap();
+        ca.write_all(cert.serialize_pem().unwrap().as_bytes())
+            .unwrap();
+        (build_uri(&format!("https://localhost:{}/", port)), ca)
+    }
+
+    fn client_with(tls: TlsSettings) -> HttpClient {
+        HttpClient::new(&ClientSettings {
+            tls: Arc::new(client_config(&tls).unwrap()),
+            ..ClientSettings::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn trusts_custom_ca() {
+        let (uri, ca) = self_signed_server().await;
+        let client = client_with(TlsSettings {
+            ca_cert: Some(ca.path().to_path_buf()),
+            ..TlsSettings::default()
+        });
+
+        let response = client
+            .send(hyper::Request::get(uri).body(Body::empty()).unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body_bytes, 6);
+        assert_eq!(client.take_tls_handshakes().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_ca() {
+        let (uri, _) = self_signed_server().await;
+        let client = client_with(TlsSettings::default());
+
+        let err = client
+            .send(hyper::Request::get(uri).body(Body::empty()).unwrap(), false)
+            .await
+            .expect_err("Untrusted certificate");
+        assert_eq!(RequestError::classify(&err), RequestError::Tls);
+    }
+
+    #[tokio::test]
+    async fn insecure_skips_verification() {
+        let (uri, _) = self_signed_server().await;
+        let client = client_with(TlsSettings {
+            insecure: true,
+            ..TlsSettings::default()
+        });
+
+        let response = client
+            .send(hyper::Request::get(uri).body(Body::empty()).unwrap(), false)
+            .await
+            .unwrap();
+        assert_eq!(response.status, 200);
+    }
+
+    #[test]
+    fn client_cert_needs_key() {
+        let settings = TlsSettings {
+            client_cert: Some(PathBuf::from("client.pem")),
+            ..TlsSettings::default()
+        };
+        assert!(settings.validate().is_err());
+        let settings = TlsSettings {
+            ca_cert: Some(PathBuf::from("missing-ca.pem")),
+            ..TlsSettings::default()
+        };
+        assert!(settings.validate().is_err());
+        assert!(TlsSettings::default().validate().is_ok());
+    }
+}
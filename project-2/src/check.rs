@@ -0,0 +1,59 @@
+// Assertions on the responses, reported as a pass rate per check
+use std::collections::BTreeMap;
+
+use benchmark::Check;
+
+/// Parses `[name=]condition`, e.g. `has token=json:$.token=abc` or `status:2xx`.
+/// Without a name the check is named after its condition.
+pub(crate) fn parse_check(s: &str) -> Result<Check, String> {
+    // An = after the kind of condition belongs to the condition, e.g. header:Name=value
+    let (name, condition) = match s.split_once('=') {
+        Some((name, condition)) if !name.contains(':') => (name.trim(), condition.trim()),
+        _ => (s.trim(), s.trim()),
+    };
+    if name.is_empty() {
+        return Err(format!("Check {:?} has an empty name", s));
+    }
+    let condition = condition
+        .parse()
+        .map_err(|e| format!("Invalid check {:?}: {}", s, e))?;
+    Ok(Check::new(name, condition))
+}
+
+/// The checks of every request followed by those of an endpoint or step,
+/// given by name in a plan. `of` names the endpoint or step in errors.
+pub(crate) fn with_own_checks(
+    checks: &[Check],
+    own: &BTreeMap<String, String>,
+    of: &str,
+) -> Result<Vec<Check>, String> {
+    let mut checks = checks.to_vec();
+    for (name, condition) in own {
+        let condition = condition
+            .parse()
+            .map_err(|e| format!("{} checks {:?}: {}", of, name, e))?;
+        checks.push(Check::new(name, condition));
+    }
+    Ok(checks)
+}
+
+#[cfg(test)]
+mod tests {
+    use benchmark::Condition;
+
+    use super::*;
+
+    #[test]
+    fn names_checks() {
+        let check = parse_check("fast = latency:250ms").unwrap();
+        assert_eq!(check.name, "fast");
+        assert!(matches!(check.condition, Condition::MaxLatency(_)));
+
+        let check = parse_check("header:Cache-Control=no-cache").unwrap();
+        assert_eq!(check.name, "header:Cache-Control=no-cache");
+        assert!(matches!(check.condition, Condition::Header(_, Some(_))));
+
+        assert!(parse_check("=status:200").is_err());
+        assert!(parse_check("ok=status").is_err());
+    }
+}
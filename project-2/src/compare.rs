@@ -0,0 +1,527 @@
+// Compares two saved reports to find regressions between runs
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::File,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use benchmark::StatusSet;
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+use serde::Deserialize;
+use statrs::distribution::{ContinuousCDF, Normal};
+use tabled::{Table, Tabled};
+
+use crate::{format_float, RawEvent};
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct CompareArgs {
+    /// Report of the reference run, as written with -o or --format json
+    baseline: PathBuf,
+
+    /// Report of the run checked against the baseline
+    candidate: PathBuf,
+
+    /// How much worse, in percent, a metric may get before it is a regression
+    #[arg(long, default_value_t = 5_f64)]
+    tolerance: f64,
+
+    /// Raw log (--raw-log) of the baseline run. With --candidate-raw, latency changes
+    /// only count when a Mann-Whitney U test finds the samples differ
+    #[arg(long, requires = "candidate_raw")]
+    baseline_raw: Option<PathBuf>,
+
+    /// Raw log of the candidate run
+    #[arg(long, requires = "baseline_raw")]
+    candidate_raw: Option<PathBuf>,
+
+    /// Largest p-value of the test at which latencies are considered different
+    #[arg(long, default_value_t = 0.05)]
+    significance: f64,
+}
+
+// The columns of a report the comparison needs, older reports may have more
+#[derive(Debug, Clone, Deserialize)]
+struct ReportRow {
+    // Missing from reports older than the endpoint mix
+    #[serde(default)]
+    endpoint: String,
+    status: String,
+    // Not known for a run that took no time
+    average_rate: Option<f64>,
+    mean: f64,
+    p90: f64,
+    p99: f64,
+}
+
+#[derive(Deserialize)]
+struct JsonStatuses {
+    // Missing from reports older than the run metadata
+    #[serde(default)]
+    metadata: JsonMetadata,
+    statuses: Vec<ReportRow>,
+}
+
+#[derive(Default, Deserialize)]
+struct JsonMetadata {
+    #[serde(default)]
+    settings: JsonSettings,
+}
+
+#[derive(Default, Deserialize)]
+struct JsonSettings {
+    // Missing from reports older than configurable success statuses
+    success_status: Option<String>,
+}
+
+struct Report {
+    rows: BTreeMap<Key, ReportRow>,
+    // The statuses the run counted as successes, CSV reports don't record them
+    success_statuses: Option<StatusSet>,
+}
+
+impl Report {
+    // Requests that got no response or a status the run did not count as a success,
+    // statuses below 400 when the report does not tell
+    fn is_error(&self, status: &str) -> bool {
+        !status
+            .parse::<u16>()
+            .is_ok_and(|status| match &self.success_statuses {
+                Some(statuses) => statuses.contains(status),
+                None => status < 400,
+            })
+    }
+}
+
+struct Metric {
+    name: &'static str,
+    value: fn(&ReportRow) -> Option<f64>,
+    // Lower is better for latencies, higher for the rate
+    latency: bool,
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "rps",
+        value: |r| r.average_rate,
+        latency: false,
+    },
+    Metric {
+        name: "mean",
+        value: |r| Some(r.mean),
+        latency: true,
+    },
+    Metric {
+        name: "p90",
+        value: |r| Some(r.p90),
+        latency: true,
+    },
+    Metric {
+        name: "p99",
+        value: |r| Some(r.p99),
+        latency: true,
+    },
+];
+
+// Rows of both reports are matched on endpoint and status
+type Key = (String, String);
+
+#[derive(Debug, Tabled)]
+struct Delta {
+    endpoint: String,
+    status: String,
+    metric: &'static str,
+    baseline: String,
+    candidate: String,
+    change: String,
+    verdict: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Verdict {
+    Same,
+    Improvement,
+    Regression,
+    // Beyond the tolerance, but the samples do not differ significantly
+    Noise,
+}
+
+/// Prints the changes between the reports, returns whether any metric regressed.
+pub(crate) fn compare(args: &CompareArgs) -> Result<bool, Box<dyn Error>> {
+    let baseline = load_report(&args.baseline)?;
+    let candidate = load_report(&args.candidate)?;
+    let p_values = match (&args.baseline_raw, &args.candidate_raw) {
+        (Some(baseline_raw), Some(candidate_raw)) => {
+            let baseline = load_samples(baseline_raw)?;
+            let candidate = load_samples(candidate_raw)?;
+            baseline
+                .iter()
+                .filter_map(|(key, samples)| {
+                    let other = candidate.get(key)?;
+                    Some((key.clone(), mann_whitney(samples, other)))
+                })
+                .collect()
+        }
+        _ => BTreeMap::new(),
+    };
+
+    let deltas = deltas(&baseline, &candidate, args.tolerance, |key| {
+        p_values.get(key).map(|&p| p < args.significance)
+    });
+    let regressions = deltas.iter().filter(|d| d.verdict == "regression").count();
+    println!("{}", Table::new(&deltas));
+    for ((endpoint, status), p) in &p_values {
+        println!("{} {}: Mann-Whitney U p-value {:.4}", endpoint, status, p);
+    }
+    match regressions {
+        0 => println!("no regressions beyond {}%", format_float(&args.tolerance)),
+        n => println!(
+            "{} regressions beyond {}%",
+            n,
+            format_float(&args.tolerance)
+        ),
+    }
+    Ok(regressions == 0)
+}
+
+fn load_report(path: &Path) -> Result<Report, Box<dyn Error>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    parse_report(&content).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+fn parse_report(content: &str) -> Result<Report, Box<dyn Error>> {
+    let (rows, success_statuses): (Vec<ReportRow>, _) = if content.trim_start().starts_with('{') {
+        let report = serde_json::from_str::<JsonStatuses>(content)?;
+        let success_statuses = match report.metadata.settings.success_status {
+            Some(statuses) => Some(statuses.parse::<StatusSet>()?),
+            None => None,
+        };
+        (report.statuses, success_statuses)
+    } else {
+        let rows = csv::Reader::from_reader(content.as_bytes())
+            .deserialize()
+            .collect::<Result<_, _>>()?;
+        (rows, None)
+    };
+    Ok(Report {
+        rows: rows
+            .into_iter()
+            .map(|r| ((r.endpoint.clone(), r.status.clone()), r))
+            .collect(),
+        success_statuses,
+    })
+}
+
+// Latencies kept per endpoint and outcome of a raw log. Enough for the test
+// to find small differences, while the memory needed stays bounded
+const MAX_SAMPLES: usize = 50_000;
+
+// A uniform random sample of at most MAX_SAMPLES of the latencies seen
+#[derive(Default)]
+struct Reservoir {
+    seen: u64,
+    samples: Vec<f64>,
+}
+
+impl Reservoir {
+    fn add(&mut self, latency: f64, rng: &mut impl Rng) {
+        self.seen += 1;
+        if self.samples.len() < MAX_SAMPLES {
+            self.samples.push(latency);
+        } else {
+            let i = rng.gen_range(0..self.seen);
+            if let Some(sample) = self.samples.get_mut(i as usize) {
+                *sample = latency;
+            }
+        }
+    }
+}
+
+// Latencies of the raw log grouped by endpoint and outcome, in the form reports name them
+fn load_samples(path: &Path) -> Result<BTreeMap<Key, Vec<f64>>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+    // Seeded so comparing the same logs again gives the same p-values
+    let mut rng = SmallRng::seed_from_u64(0);
+    let mut reservoirs: BTreeMap<Key, Reservoir> = BTreeMap::new();
+    for line in BufReader::new(file).lines() {
+        let event: RawEvent = serde_json::from_str(&line?)?;
+        reservoirs
+            .entry((event.endpoint, event.outcome))
+            .or_default()
+            .add(event.latency, &mut rng);
+    }
+    Ok(reservoirs
+        .into_iter()
+        .map(|(key, reservoir)| (key, reservoir.samples))
+        .collect())
+}
+
+// `differs` tells whether the latencies of an endpoint and status differ
+// significantly, `None` when there are no samples to tell
+fn deltas(
+    baseline: &Report,
+    candidate: &Report,
+    tolerance: f64,
+    differs: impl Fn(&Key) -> Option<bool>,
+) -> Vec<Delta> {
+    let mut keys: Vec<&Key> = baseline.rows.keys().chain(candidate.rows.keys()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut deltas = vec![];
+    for key in keys {
+        let (before, after) = (baseline.rows.get(key), candidate.rows.get(key));
+        // Whether the status is an error is up to the run the row comes from,
+        // the candidate when both have it
+        let error = match after {
+            Some(_) => candidate.is_error(&key.1),
+            None => baseline.is_error(&key.1),
+        };
+        for &Metric {
+            name: metric,
+            value,
+            latency,
+        } in METRICS
+        {
+            let (before, after) = (before.and_then(value), after.and_then(value));
+            let verdict = match (before, after) {
+                (Some(before), Some(after)) => {
+                    // More errors per second is worse
+                    let verdict = judge(before, after, tolerance, !latency && !error);
+                    match (verdict, latency.then(|| differs(key)).flatten()) {
+                        (Verdict::Regression | Verdict::Improvement, Some(false)) => Verdict::Noise,
+                        (verdict, _) => verdict,
+                    }
+                }
+                // A row only one of the reports has is judged on its rate:
+                // an error that appears or a success that vanishes is a regression
+                (None, Some(_)) if !latency && error => Verdict::Regression,
+                (Some(_), None) if !latency => {
+                    if error {
+                        Verdict::Improvement
+                    } else {
+                        Verdict::Regression
+                    }
+                }
+                _ => Verdict::Same,
+            };
+            let show = |v: Option<f64>| v.as_ref().map_or(String::from("-"), format_float);
+            deltas.push(Delta {
+                endpoint: key.0.clone(),
+                status: key.1.clone(),
+                metric,
+                baseline: show(before),
+                candidate: show(after),
+                change: match (before, after) {
+                    (Some(before), Some(after)) if before != 0_f64 => {
+                        format!("{:+.2}%", change(before, after))
+                    }
+                    _ => String::from("-"),
+                },
+                verdict: String::from(match verdict {
+                    Verdict::Same => "",
+                    Verdict::Improvement => "improvement",
+                    Verdict::Regression => "regression",
+                    Verdict::Noise => "noise",
+                }),
+            });
+        }
+    }
+    deltas
+}
+
+fn change(before: f64, after: f64) -> f64 {
+    (after - before) * 100_f64 / before
+}
+
+fn judge(before: f64, after: f64, tolerance: f64, higher_is_better: bool) -> Verdict {
+    // Any change from nothing is beyond the tolerance
+    if before == 0_f64 {
+        return match (after > 0_f64, higher_is_better) {
+            (false, _) => Verdict::Same,
+            (true, true) => Verdict::Improvement,
+            (true, false) => Verdict::Regression,
+        };
+    }
+    let change = change(before, after);
+    let worse = if higher_is_better { -change } else { change };
+    if worse > tolerance {
+        Verdict::Regression
+    } else if -worse > tolerance {
+        Verdict::Improvement
+    } else {
+        Verdict::Same
+    }
+}
+
+/// Two-sided p-value of the Mann-Whitney U test, with the normal approximation
+/// corrected for ties. Small when one sample tends to be larger than the other.
+fn mann_whitney(first: &[f64], second: &[f64]) -> f64 {
+    let (n1, n2) = (first.len() as f64, second.len() as f64);
+    if first.is_empty() || second.is_empty() {
+        return 1_f64;
+    }
+    let mut values: Vec<(f64, bool)> = first
+        .iter()
+        .map(|&v| (v, true))
+        .chain(second.iter().map(|&v| (v, false)))
+        .collect();
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    // Sum of the ranks of the first sample, tied values share their average rank
+    let mut rank_sum = 0_f64;
+    let mut ties = 0_f64;
+    let mut i = 0;
+    while i < values.len() {
+        let mut j = i;
+        while j < values.len() && values[j].0 == values[i].0 {
+            j += 1;
+        }
+        let rank = (i + j + 1) as f64 / 2_f64;
+        let count = (j - i) as f64;
+        rank_sum += rank * values[i..j].iter().filter(|v| v.1).count() as f64;
+        ties += count * count * count - count;
+        i = j;
+    }
+
+    let n = n1 + n2;
+    let u = rank_sum - n1 * (n1 + 1_f64) / 2_f64;
+    let mean = n1 * n2 / 2_f64;
+    let variance = n1 * n2 / 12_f64 * ((n + 1_f64) - ties / (n * (n - 1_f64)));
+    if variance <= 0_f64 {
+        // Every value is the same
+        return 1_f64;
+    }
+    let z = ((u - mean).abs() - 0.5).max(0_f64) / variance.sqrt();
+    let normal = Normal::new(0_f64, 1_f64).expect("Standard normal distribution");
+    2_f64 * (1_f64 - normal.cdf(z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(rps: f64, p99: f64) -> Report {
+        let row = ReportRow {
+            endpoint: String::from("GET /"),
+            status: String::from("200"),
+            average_rate: Some(rps),
+            mean: p99 / 2_f64,
+            p90: p99 / 2_f64,
+            p99,
+        };
+        Report {
+            rows: BTreeMap::from([((row.endpoint.clone(), row.status.clone()), row)]),
+            success_statuses: None,
+        }
+    }
+
+    fn verdict(deltas: &[Delta], metric: &str) -> String {
+        deltas
+            .iter()
+            .find(|d| d.metric == metric)
+            .unwrap()
+            .verdict
+            .clone()
+    }
+
+    fn deltas_with(differs: impl Fn(&Key) -> Option<bool>) -> Vec<Delta> {
+        deltas(&report(1000.0, 100.0), &report(1200.0, 120.0), 5.0, differs)
+    }
+
+    #[test]
+    fn flags_changes_beyond_tolerance() {
+        let deltas = deltas(&report(1000.0, 100.0), &report(980.0, 120.0), 5.0, |_| None);
+        assert_eq!(verdict(&deltas, "rps"), "");
+        assert_eq!(verdict(&deltas, "p99"), "regression");
+        assert_eq!(deltas[3].change, "+20.00%");
+
+        let deltas = deltas_with(|_| Some(false));
+        assert_eq!(verdict(&deltas, "p99"), "noise");
+        // The rate is not tested on samples
+        assert_eq!(verdict(&deltas, "rps"), "improvement");
+    }
+
+    #[test]
+    fn flags_rows_that_appear_or_vanish() {
+        let mut candidate = report(1000.0, 100.0);
+        let errors = ReportRow {
+            status: String::from("connect_refused"),
+            ..candidate.rows[&(String::from("GET /"), String::from("200"))].clone()
+        };
+        candidate
+            .rows
+            .insert((errors.endpoint.clone(), errors.status.clone()), errors);
+
+        let appeared = deltas(&report(1000.0, 100.0), &candidate, 5.0, |_| None);
+        let refused = |d: &&Delta| d.status == "connect_refused" && d.metric == "rps";
+        assert_eq!(appeared.iter().find(refused).unwrap().verdict, "regression");
+        let vanished = deltas(&candidate, &report(1000.0, 100.0), 5.0, |_| None);
+        assert_eq!(
+            vanished.iter().find(refused).unwrap().verdict,
+            "improvement"
+        );
+
+        let empty = Report {
+            rows: BTreeMap::new(),
+            success_statuses: None,
+        };
+        let gone = deltas(&report(1000.0, 100.0), &empty, 5.0, |_| None);
+        assert_eq!(verdict(&gone, "rps"), "regression");
+
+        // Errors going up from none, or more often, are regressions as well
+        assert_eq!(judge(0.0, 3.0, 5.0, false), Verdict::Regression);
+        assert_eq!(judge(0.0, 0.0, 5.0, false), Verdict::Same);
+        assert!(empty.is_error("500") && empty.is_error("timeout") && !empty.is_error("204"));
+    }
+
+    #[test]
+    fn uses_the_success_statuses_of_the_report() {
+        let json = r#"{
+            "metadata": {"settings": {"success_status": "2xx,404"}},
+            "statuses": [{"endpoint": "GET /", "status": "404", "average_rate": 50.0,
+                          "mean": 1.0, "p90": 1.0, "p99": 1.0}]
+        }"#;
+        let candidate = parse_report(json).unwrap();
+        assert!(!candidate.is_error("404") && candidate.is_error("302"));
+
+        // A 404 the run counted as a success is not a new error
+        let deltas = deltas(&report(1000.0, 100.0), &candidate, 5.0, |_| None);
+        let not_found = |d: &&Delta| d.status == "404" && d.metric == "rps";
+        assert_eq!(deltas.iter().find(not_found).unwrap().verdict, "");
+
+        // Reports without them fall back to statuses below 400
+        let json = r#"{"metadata": {"settings": {}}, "statuses": []}"#;
+        assert!(parse_report(json).unwrap().is_error("404"));
+        let csv = "endpoint,status,average_rate,mean,p90,p99\nGET /,404,50,1,1,1\n";
+        assert!(parse_report(csv).unwrap().is_error("404"));
+    }
+
+    #[test]
+    fn mann_whitney_tells_shifted_samples_apart() {
+        let base: Vec<f64> = (0..200).map(|i| (i % 50) as f64).collect();
+        let same: Vec<f64> = (0..200).map(|i| ((i + 7) % 50) as f64).collect();
+        let slower: Vec<f64> = base.iter().map(|v| v + 10_f64).collect();
+
+        assert!(mann_whitney(&base, &same) > 0.5);
+        assert!(mann_whitney(&base, &slower) < 0.001);
+        assert_eq!(mann_whitney(&[1.0; 10], &[1.0; 10]), 1_f64);
+    }
+
+    #[test]
+    fn reservoir_keeps_a_bounded_uniform_sample() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let mut reservoir = Reservoir::default();
+        for i in 0..MAX_SAMPLES * 4 {
+            reservoir.add(i as f64, &mut rng);
+        }
+
+        assert_eq!(reservoir.samples.len(), MAX_SAMPLES);
+        // Later latencies replace earlier ones as often as they should
+        let mean = reservoir.samples.iter().sum::<f64>() / MAX_SAMPLES as f64;
+        let expected = (MAX_SAMPLES * 2) as f64;
+        assert!((mean - expected).abs() < expected * 0.02);
+    }
+}
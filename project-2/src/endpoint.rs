@@ -0,0 +1,173 @@
+// The traffic mix: which requests to send and how often
+use std::{collections::BTreeMap, error::Error, str::FromStr};
+
+use benchmark::Endpoint;
+use serde::Deserialize;
+
+use crate::{build_request, check::with_own_checks, Args};
+
+/// One entry of the mix, given as `name=weight:[METHOD ]uri` on the command line
+/// or as a table in a plan. A URI starting with `/` is a path on the target.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct EndpointArg {
+    pub(crate) name: String,
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32,
+    // --method when not set
+    pub(crate) method: Option<String>,
+    pub(crate) uri: String,
+    // Sent along with the headers of --header
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+    // Replaces --body, "@path" reads it from a file
+    pub(crate) body: Option<String>,
+    // Check name to condition, run along with those of --check
+    #[serde(default)]
+    pub(crate) checks: BTreeMap<String, String>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl FromStr for EndpointArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Endpoint {:?} is not in the form 'name=weight:[METHOD ]uri'",
+                s
+            )
+        };
+        let (name, rest) = s.split_once('=').ok_or_else(invalid)?;
+        let (weight, request) = rest.split_once(':').ok_or_else(invalid)?;
+        let weight = weight
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid weight in endpoint {:?}", s))?;
+        let (method, uri) = match request.trim().split_once(' ') {
+            Some((method, uri)) => (Some(method.to_string()), uri.trim()),
+            None => (None, request.trim()),
+        };
+        if name.trim().is_empty() || uri.is_empty() {
+            return Err(invalid());
+        }
+        Ok(EndpointArg {
+            name: name.trim().to_string(),
+            weight,
+            method,
+            uri: uri.to_string(),
+            headers: BTreeMap::new(),
+            body: None,
+            checks: BTreeMap::new(),
+        })
+    }
+}
+
+/// The endpoints of the mix, or the single request of --target-uri without one.
+pub(crate) fn build_endpoints(args: &Args) -> Result<Vec<Endpoint>, Box<dyn Error>> {
+    if args.endpoints.is_empty() {
+        let request = build_request(
+            args.target_uri(),
+            &args.method,
+            &args.headers,
+            args.body.as_deref(),
+        )?;
+        let name = format!("{} {}", request.method, split_uri(args.target_uri()).2);
+        let mut endpoint = Endpoint::new(name, request);
+        endpoint.checks = args.checks.clone();
+        return Ok(vec![endpoint]);
+    }
+
+    let mut names = vec![];
+    args.endpoints
+        .iter()
+        .map(|endpoint| {
+            if names.contains(&&endpoint.name) {
+                return Err(format!("Endpoint name {:?} is used twice", endpoint.name).into());
+            }
+            names.push(&endpoint.name);
+            let uri = resolve_uri(args.target_uri(), &endpoint.uri);
+            let headers: Vec<String> = args
+                .headers
+                .iter()
+                .cloned()
+                .chain(
+                    endpoint
+                        .headers
+                        .iter()
+                        .map(|(name, value)| format!("{}: {}", name, value)),
+                )
+                .collect();
+            let request = build_request(
+                &uri,
+                endpoint.method.as_deref().unwrap_or(&args.method),
+                &headers,
+                endpoint.body.as_deref().or(args.body.as_deref()),
+            )?;
+            let checks = with_own_checks(
+                &args.checks,
+                &endpoint.checks,
+                &format!("Endpoint {:?}", endpoint.name),
+            )?;
+            let mut built = Endpoint::new(&endpoint.name, request).with_weight(endpoint.weight);
+            built.checks = checks;
+            Ok(built)
+        })
+        .collect()
+}
+
+/// A URI starting with `/` is a path on the target, any other is left as is.
+pub(crate) fn resolve_uri(target: &str, uri: &str) -> String {
+    if uri.starts_with('/') {
+        let (scheme, authority, _) = split_uri(target);
+        format!("{}://{}{}", scheme, authority, uri)
+    } else {
+        uri.to_string()
+    }
+}
+
+// Scheme, authority and path of a URI that may still hold {{name}} placeholders
+fn split_uri(uri: &str) -> (&str, &str, &str) {
+    let (scheme, rest) = uri.split_once("://").unwrap_or(("http", uri));
+    let (authority, path) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    let path = path.split('?').next().unwrap_or_default();
+    (scheme, authority, if path.is_empty() { "/" } else { path })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_weighted_endpoints() {
+        let read: EndpointArg = "person=70:/person".parse().unwrap();
+        assert_eq!(read.name, "person");
+        assert_eq!(read.weight, 70);
+        assert_eq!(read.method, None);
+        assert_eq!(read.uri, "/person");
+
+        let write: EndpointArg = "write=10:POST http://localhost/person?x=1".parse().unwrap();
+        assert_eq!(write.method.as_deref(), Some("POST"));
+        assert_eq!(write.uri, "http://localhost/person?x=1");
+
+        assert!("person:/person".parse::<EndpointArg>().is_err());
+        assert!("person=many:/person".parse::<EndpointArg>().is_err());
+        assert!("=1:/person".parse::<EndpointArg>().is_err());
+    }
+
+    #[test]
+    fn resolves_paths_on_the_target() {
+        let target = "https://localhost:8443/{{tenant}}/person?page=1";
+        assert_eq!(
+            resolve_uri(target, "/search"),
+            "https://localhost:8443/search"
+        );
+        assert_eq!(resolve_uri("localhost:8080", "/"), "http://localhost:8080/");
+        assert_eq!(resolve_uri(target, "http://other/"), "http://other/");
+        assert_eq!(split_uri(target).2, "/{{tenant}}/person");
+        assert_eq!(split_uri("http://localhost?x=1").2, "/");
+    }
+}
@@ -0,0 +1,199 @@
+// Test data: rows of variables filled in the {{name}} placeholders of the requests
+use std::{error::Error, fs::File, io::BufRead, io::BufReader, path::PathBuf, str::FromStr};
+
+use benchmark::{FeedOrder, FeedScope, Feeder, Variables};
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::Args;
+
+/// A CSV file with a header row or a JSON lines file, given as
+/// `path[,sequential|random|circular][,request|user][,stop]` on the command line
+/// or as a table in a plan.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FeederArg {
+    pub(crate) file: PathBuf,
+    #[serde(default)]
+    pub(crate) order: FeedOrderArg,
+    // Whether a row is taken for every request or for every virtual user
+    #[serde(default)]
+    pub(crate) per: FeedScopeArg,
+    // End the run once every row was used. Sequential feeders always do,
+    // random ones otherwise keep picking rows and circular ones cannot
+    #[serde(default)]
+    pub(crate) stop: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FeedOrderArg {
+    Sequential,
+    Random,
+    #[default]
+    Circular,
+}
+
+impl From<FeedOrderArg> for FeedOrder {
+    fn from(value: FeedOrderArg) -> Self {
+        match value {
+            FeedOrderArg::Sequential => FeedOrder::Sequential,
+            FeedOrderArg::Random => FeedOrder::Random,
+            FeedOrderArg::Circular => FeedOrder::Circular,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FeedScopeArg {
+    #[default]
+    Request,
+    User,
+}
+
+impl From<FeedScopeArg> for FeedScope {
+    fn from(value: FeedScopeArg) -> Self {
+        match value {
+            FeedScopeArg::Request => FeedScope::Request,
+            FeedScopeArg::User => FeedScope::User,
+        }
+    }
+}
+
+impl FromStr for FeederArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = s.split(',').map(str::trim);
+        let file = options
+            .next()
+            .filter(|file| !file.is_empty())
+            .ok_or_else(|| {
+                format!(
+                    "Feeder {:?} is not in the form 'path[,order][,request|user][,stop]'",
+                    s
+                )
+            })?;
+        let mut feeder = FeederArg {
+            file: file.into(),
+            order: FeedOrderArg::default(),
+            per: FeedScopeArg::default(),
+            stop: false,
+        };
+        for option in options {
+            if option == "stop" {
+                feeder.stop = true;
+            } else if let Ok(order) = FeedOrderArg::from_str(option, true) {
+                feeder.order = order;
+            } else if let Ok(per) = FeedScopeArg::from_str(option, true) {
+                feeder.per = per;
+            } else {
+                return Err(format!(
+                    "Unknown feeder option {:?}, expected sequential, random, circular, request, user or stop",
+                    option
+                ));
+            }
+        }
+        Ok(feeder)
+    }
+}
+
+/// The feeders of --feeder, with all their rows read up front.
+pub(crate) fn build_feeders(args: &Args) -> Result<Vec<Feeder>, Box<dyn Error>> {
+    args.feeders
+        .iter()
+        .map(|arg| {
+            let name = arg.file.display().to_string();
+            let rows = read_rows(arg).map_err(|e| format!("cannot read feeder {}: {}", name, e))?;
+            Ok(Feeder::new(
+                name,
+                rows,
+                arg.order.into(),
+                arg.per.into(),
+                arg.stop,
+            )?)
+        })
+        .collect()
+}
+
+// JSON lines for .jsonl and .ndjson files, CSV otherwise
+fn read_rows(arg: &FeederArg) -> Result<Vec<Variables>, Box<dyn Error>> {
+    let file = File::open(&arg.file)?;
+    match arg.file.extension().and_then(|e| e.to_str()) {
+        Some("jsonl" | "ndjson") => read_json_lines(BufReader::new(file)),
+        _ => read_csv(file),
+    }
+}
+
+fn read_csv(reader: impl std::io::Read) -> Result<Vec<Variables>, Box<dyn Error>> {
+    let mut reader = csv::Reader::from_reader(reader);
+    let columns = reader.headers()?.clone();
+    reader
+        .records()
+        .map(|record| {
+            Ok(columns
+                .iter()
+                .zip(record?.iter())
+                .map(|(column, value)| (column.to_string(), value.to_string()))
+                .collect())
+        })
+        .collect()
+}
+
+// One object per line, values other than strings are taken as written in JSON
+fn read_json_lines(reader: impl BufRead) -> Result<Vec<Variables>, Box<dyn Error>> {
+    let mut rows = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object = match serde_json::from_str(&line)? {
+            Value::Object(object) => object,
+            _ => return Err(format!("line {} is not a JSON object", i + 1).into()),
+        };
+        rows.push(
+            object
+                .into_iter()
+                .map(|(key, value)| match value {
+                    Value::String(s) => (key, s),
+                    value => (key, value.to_string()),
+                })
+                .collect(),
+        );
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feeders_and_their_rows() {
+        let feeder: FeederArg = "users.csv,random,user,stop".parse().unwrap();
+        assert_eq!(feeder.file, PathBuf::from("users.csv"));
+        assert_eq!(feeder.order, FeedOrderArg::Random);
+        assert_eq!(feeder.per, FeedScopeArg::User);
+        assert!(feeder.stop);
+        let feeder: FeederArg = "ids.jsonl".parse().unwrap();
+        assert_eq!(feeder.order, FeedOrderArg::Circular);
+        assert_eq!(feeder.per, FeedScopeArg::Request);
+        assert!(!feeder.stop);
+        assert!("ids.csv,forever".parse::<FeederArg>().is_err());
+        assert!(",stop".parse::<FeederArg>().is_err());
+
+        let rows = read_csv(&b"id,name\n1,Ada\n2,\"Grace, H\"\n"[..]).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1]["name"], "Grace, H");
+
+        let rows =
+            read_json_lines(&b"{\"id\": 1, \"name\": \"Ada\"}\n\n{\"id\": 2}\n"[..]).unwrap();
+        assert_eq!(rows[0]["id"], "1");
+        assert_eq!(rows[0]["name"], "Ada");
+        assert_eq!(rows.len(), 2);
+        assert!(read_json_lines(&b"[1]\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+// A single self-contained HTML page with SVG charts, it needs neither
+// scripts nor network access to be viewed
+use std::fmt::Write;
+
+use benchmark::{OutcomeStats, Snapshot};
+
+use crate::{format_float, RunMetadata, StatusStatistics};
+
+const WIDTH: f64 = 720.0;
+const HEIGHT: f64 = 280.0;
+// Room for the axis labels around the plot
+const LEFT: f64 = 70.0;
+const RIGHT: f64 = 20.0;
+const TOP: f64 = 20.0;
+const BOTTOM: f64 = 50.0;
+const COLORS: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#d62728", "#2ca02c", "#9467bd", "#8c564b",
+];
+const HISTOGRAM_BUCKETS: usize = 30;
+
+pub(crate) fn render(
+    metadata: &RunMetadata,
+    statistics: &[StatusStatistics],
+    snapshots: &[Snapshot],
+    all: &OutcomeStats,
+) -> String {
+    let throughput = vec![Series {
+        name: "requests/s",
+        points: snapshots
+            .iter()
+            .map(|s| (s.elapsed.as_secs_f64(), s.rps()))
+            .collect(),
+    }];
+    let percentile = |name, pick: fn(&Snapshot) -> std::time::Duration| Series {
+        name,
+        points: snapshots
+            .iter()
+            .map(|s| (s.elapsed.as_secs_f64(), pick(s).as_secs_f64() * 1000.0))
+            .collect(),
+    };
+    let latency = vec![
+        percentile("p50", |s| s.p50),
+        percentile("p90", |s| s.p90),
+        percentile("p99", |s| s.p99),
+    ];
+    // Endpoints only need telling apart when there are several of them
+    let several = statistics
+        .windows(2)
+        .any(|w| w[0].endpoint != w[1].endpoint);
+    let statuses: Vec<(String, f64)> = statistics
+        .iter()
+        .map(|s| {
+            if several {
+                (format!("{} {}", s.endpoint, s.status), s.requests as f64)
+            } else {
+                (s.status.clone(), s.requests as f64)
+            }
+        })
+        .collect();
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Load test {target}</title>\n\
+         <style>\
+         body {{ font-family: sans-serif; margin: 2em; color: #222; }}\
+         table {{ border-collapse: collapse; margin-bottom: 2em; }}\
+         td, th {{ border: 1px solid #ccc; padding: 4px 8px; text-align: right; }}\
+         svg {{ display: block; margin-bottom: 2em; }}\
+         </style>\n</head>\n<body>\n<h1>Load test {target}</h1>\n<p>{method} started {start}, ran {total} s</p>\n",
+        target = escape(&metadata.target),
+        method = escape(&metadata.method),
+        start = metadata.start_time.as_deref().unwrap_or("-"),
+        total = format_float(&metadata.total_time.unwrap_or_default()),
+    );
+    html.push_str(&status_table(statistics));
+    html.push_str("<h2>Throughput over time</h2>\n");
+    html.push_str(&line_chart(&throughput, "seconds", "requests/s"));
+    html.push_str("<h2>Latency percentiles over time</h2>\n");
+    html.push_str(&line_chart(&latency, "seconds", "ms"));
+    html.push_str("<h2>Latency distribution</h2>\n");
+    html.push_str(&bar_chart(&latency_histogram(all), "ms", "requests"));
+    html.push_str("<h2>Status codes</h2>\n");
+    html.push_str(&bar_chart(&statuses, "status", "requests"));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+struct Series {
+    name: &'static str,
+    points: Vec<(f64, f64)>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn status_table(statistics: &[StatusStatistics]) -> String {
+    let mut table = String::from(
+        "<table>\n<tr><th>endpoint</th><th>status</th><th>requests</th><th>percent</th><th>mean ms</th>\
+         <th>p90 ms</th><th>p99 ms</th><th>p99 corrected ms</th></tr>\n",
+    );
+    for s in statistics {
+        let _ = writeln!(
+            table,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&s.endpoint),
+            escape(&s.status),
+            s.requests,
+            format_float(&s.percent),
+            format_float(&s.mean),
+            format_float(&s.p90),
+            format_float(&s.p99),
+            format_float(&s.p99_corrected),
+        );
+    }
+    table.push_str("</table>\n");
+    table
+}
+
+// Counts of the recorded latencies in buckets of growing width, so both
+// the bulk of the requests and the tail stay visible
+fn latency_histogram(all: &OutcomeStats) -> Vec<(String, f64)> {
+    let latency = &all.latency;
+    if latency.is_empty() {
+        return vec![];
+    }
+    let low = (latency.min().max(1)) as f64;
+    let high = (latency.max() as f64).max(low + 1.0);
+    let ratio = (high / low).powf(1.0 / HISTOGRAM_BUCKETS as f64);
+    let mut counts = vec![0_u64; HISTOGRAM_BUCKETS];
+    for value in latency.iter_recorded() {
+        let position = (value.value_iterated_to() as f64 / low).ln() / ratio.ln();
+        let bucket = (position.max(0.0) as usize).min(HISTOGRAM_BUCKETS - 1);
+        counts[bucket] += value.count_at_value();
+    }
+    counts
+        .into_iter()
+        .enumerate()
+        .map(|(i, count)| {
+            let upper = low * ratio.powi(i as i32 + 1) / 1000.0;
+            (format_float(&upper), count as f64)
+        })
+        .collect()
+}
+
+fn svg_open(x_label: &str, y_label: &str, y_max: f64) -> String {
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{WIDTH}\" height=\"{HEIGHT}\" font-size=\"11\">"
+    );
+    // Horizontal grid lines with the y axis values
+    for i in 0..=4 {
+        let value = y_max * i as f64 / 4.0;
+        let y = plot_y(value, y_max);
+        let _ = writeln!(
+            svg,
+            "<line x1=\"{LEFT}\" y1=\"{y:.1}\" x2=\"{}\" y2=\"{y:.1}\" stroke=\"#eee\"/>\
+             <text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            WIDTH - RIGHT,
+            LEFT - 6.0,
+            y + 4.0,
+            format_float(&value)
+        );
+    }
+    let _ = writeln!(
+        svg,
+        "<line x1=\"{LEFT}\" y1=\"{TOP}\" x2=\"{LEFT}\" y2=\"{bottom}\" stroke=\"#888\"/>\
+         <line x1=\"{LEFT}\" y1=\"{bottom}\" x2=\"{right}\" y2=\"{bottom}\" stroke=\"#888\"/>\
+         <text x=\"{center}\" y=\"{}\" text-anchor=\"middle\">{}</text>\
+         <text x=\"14\" y=\"{middle}\" text-anchor=\"middle\" transform=\"rotate(-90 14 {middle})\">{}</text>",
+        HEIGHT - 8.0,
+        escape(x_label),
+        escape(y_label),
+        bottom = HEIGHT - BOTTOM,
+        right = WIDTH - RIGHT,
+        center = LEFT + (WIDTH - LEFT - RIGHT) / 2.0,
+        middle = TOP + (HEIGHT - TOP - BOTTOM) / 2.0,
+    );
+    svg
+}
+
+fn plot_y(value: f64, y_max: f64) -> f64 {
+    HEIGHT - BOTTOM - value / y_max * (HEIGHT - TOP - BOTTOM)
+}
+
+// Rounds the top of the y axis up so the highest point is not on the edge
+fn axis_max(max: f64) -> f64 {
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let magnitude = 10_f64.powf(max.log10().floor());
+    (max / magnitude).ceil() * magnitude
+}
+
+fn line_chart(series: &[Series], x_label: &str, y_label: &str) -> String {
+    let points = series.iter().flat_map(|s| s.points.iter());
+    let x_max = points.clone().map(|p| p.0).fold(0.0, f64::max).max(1.0);
+    let y_max = axis_max(points.map(|p| p.1).fold(0.0, f64::max));
+    let mut svg = svg_open(x_label, y_label, y_max);
+    let plot_x = |x: f64| LEFT + x / x_max * (WIDTH - LEFT - RIGHT);
+    for i in 0..=4 {
+        let x = x_max * i as f64 / 4.0;
+        let _ = writeln!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            plot_x(x),
+            HEIGHT - BOTTOM + 16.0,
+            format_float(&x)
+        );
+    }
+    for (i, s) in series.iter().enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        let path: Vec<String> = s
+            .points
+            .iter()
+            .map(|&(x, y)| format!("{:.1},{:.1}", plot_x(x), plot_y(y, y_max)))
+            .collect();
+        let _ = writeln!(
+            svg,
+            "<polyline fill=\"none\" stroke=\"{color}\" stroke-width=\"2\" points=\"{}\"/>\
+             <text x=\"{}\" y=\"{}\" fill=\"{color}\">{}</text>",
+            path.join(" "),
+            LEFT + 10.0 + 80.0 * i as f64,
+            TOP - 6.0,
+            s.name
+        );
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn bar_chart(bars: &[(String, f64)], x_label: &str, y_label: &str) -> String {
+    let y_max = axis_max(bars.iter().map(|b| b.1).fold(0.0, f64::max));
+    let mut svg = svg_open(x_label, y_label, y_max);
+    let slot = (WIDTH - LEFT - RIGHT) / bars.len().max(1) as f64;
+    // Only label every few bars when there are many of them
+    let label_every = (bars.len() / 10).max(1);
+    for (i, (label, value)) in bars.iter().enumerate() {
+        let x = LEFT + slot * i as f64;
+        let y = plot_y(*value, y_max);
+        let _ = writeln!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{y:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\">\
+             <title>{}: {}</title></rect>",
+            x + slot * 0.1,
+            slot * 0.8,
+            HEIGHT - BOTTOM - y,
+            COLORS[0],
+            escape(label),
+            value
+        );
+        if i % label_every == 0 {
+            let _ = writeln!(
+                svg,
+                "<text x=\"{:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+                x + slot / 2.0,
+                HEIGHT - BOTTOM + 16.0,
+                escape(label)
+            );
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use benchmark::{Outcome, RequestSummary};
+
+    use super::*;
+
+    #[test]
+    fn latency_histogram_counts_every_request() {
+        let mut all = OutcomeStats::new(benchmark::DEFAULT_PRECISION);
+        let now = Instant::now();
+        for i in 1..=1000 {
+            all.record(&RequestSummary {
+                intended_at: now,
+                started_at: now,
+                expected_interval: None,
+                latency: Duration::from_micros(i * 100),
+                time_to_first_byte: Duration::ZERO,
+                body_bytes: 0,
+                outcome: Outcome::Status(200),
+            });
+        }
+
+        let buckets = latency_histogram(&all);
+        assert_eq!(buckets.len(), HISTOGRAM_BUCKETS);
+        assert_eq!(buckets.iter().map(|b| b.1).sum::<f64>(), 1000.0);
+    }
+
+    #[test]
+    fn escapes_markup() {
+        assert_eq!(
+            escape("<a href=\"x\">&"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;"
+        );
+    }
+}
@@ -1,15 +1,59 @@
-use std::{collections::HashMap, error::Error, fs::File, ops::RangeInclusive, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt::Display,
+    fs::File,
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
 
-use benchmark::{BenchmarkResult, BenchmarkSettings, BenchmarkStats};
-use clap::Parser;
+use benchmark::{
+    BenchmarkResult, BenchmarkSettings, BenchmarkStats, Check, IterationOutcome, Outcome,
+    OutcomeStats, RequestError, RequestEvent, RequestTemplate, Snapshot, Stage, StageTarget,
+    StatusSet, TlsSettings,
+};
+use clap::{ArgAction, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use csv::Writer;
 use indicatif::{ProgressBar, ProgressStyle};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use statrs::statistics::{OrderStatistics, Statistics};
-use tabled::{Table, Tabled};
+use tabled::{Style, Table, Tabled};
+use tokio::sync::mpsc::{channel, Receiver};
+
+mod check;
+mod compare;
+mod endpoint;
+mod feeder;
+mod html;
+mod plan;
+mod scenario;
+mod threshold;
+
+use check::parse_check;
+use compare::CompareArgs;
+use endpoint::{build_endpoints, EndpointArg};
+use feeder::{build_feeders, FeederArg};
+use plan::{Plan, RunArgs};
+use scenario::{build_scenario, StepArg};
+use threshold::Threshold;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+// The target can come from a plan with the run subcommand, it is only required without one
+#[command(mut_arg("target_uri", |arg| arg.required(true)))]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    args: Args,
+}
+
+#[derive(clap::Args, Debug)]
 struct Args {
     #[arg(short, long, default_value_t = 512, value_parser = connection_in_range)]
     connections: u16,
@@ -17,11 +61,182 @@ struct Args {
     #[arg(short, long, default_value_t = 100_000)]
     requests: u64,
 
+    /// Run for a fixed time (e.g. "30s", "5m") instead of a number of requests
+    #[arg(short, long, value_parser = humantime::parse_duration, conflicts_with = "requests")]
+    duration: Option<Duration>,
+
+    /// Send requests at a fixed rate (requests per second) regardless of response times,
+    /// using the connections as a pool of workers
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    rate: Option<u32>,
+
+    /// Give up on a request without a response after this long (e.g. "500ms", "30s")
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    timeout: Duration,
+
+    /// Give up on establishing a connection after this long
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "10s")]
+    connect_timeout: Duration,
+
+    /// Write the report to this file instead of the standard output
     #[arg(short, long)]
     output_file: Option<String>,
 
+    /// Format of the report, csv when writing to a file and a table otherwise
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
     #[arg(short, long)]
-    target_uri: String,
+    target_uri: Option<String>,
+
+    /// HTTP method of every request
+    #[arg(short = 'X', long, default_value = "GET")]
+    method: String,
+
+    /// Header added to every request, as "Name: value" (can be repeated)
+    #[arg(short = 'H', long = "header")]
+    headers: Vec<String>,
+
+    /// Request body, or @path to read it from a file. The URI, headers and body may
+    /// use values generated for every request: {{uuid}}, {{random_int(1,1000)}},
+    /// {{random_string(16)}}, {{seq}}, {{now_ms}}, and {{env.NAME}} for an environment variable.
+    /// Write {{{{ for a literal {{
+    #[arg(long)]
+    body: Option<String>,
+
+    /// Endpoint of a weighted traffic mix as "name=weight:[METHOD ]uri", a uri starting
+    /// with / is a path on the target, e.g. "person=70:/person" (can be repeated)
+    #[arg(long = "endpoint")]
+    endpoints: Vec<EndpointArg>,
+
+    // Steps of a scenario, they can only be given in a plan
+    #[arg(skip)]
+    steps: Vec<StepArg>,
+
+    /// CSV or JSON lines file whose rows fill in the {{column}} placeholders of the
+    /// URI, headers and body, as "path[,sequential|random|circular][,request|user][,stop]".
+    /// A row is taken per request or per virtual user. Circular, the default, starts over
+    /// after the last row; sequential uses every row once then ends the run; random picks
+    /// rows at random, or every row once in a random order then ends the run with "stop"
+    /// (can be repeated)
+    #[arg(long = "feeder")]
+    feeders: Vec<FeederArg>,
+
+    /// Check every response against a condition, as "[name=]condition" with a condition
+    /// of "status:200,2xx", "header:Name[=value]", "body:text", "json:$.path=value" or
+    /// "latency:250ms". The report shows the pass rate of every check (can be repeated)
+    #[arg(long = "check", value_parser = parse_check)]
+    checks: Vec<Check>,
+
+    /// Statuses counted as successes, as codes and classes like "2xx,304". Any other
+    /// status, and requests without a response, count towards the error rate
+    #[arg(long = "success-status", default_value = "2xx")]
+    success_statuses: StatusSet,
+
+    /// PEM file with extra certificate authorities to trust for https targets
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS
+    #[arg(long, requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[arg(long, requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// Do not verify the server certificate
+    #[arg(short = 'k', long, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", action = ArgAction::Set)]
+    insecure: bool,
+
+    /// Speak HTTP/2, with prior knowledge for http targets and ALPN for https targets
+    #[arg(long, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", action = ArgAction::Set)]
+    http2: bool,
+
+    /// Concurrent requests multiplexed on each HTTP/2 connection
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), requires = "http2")]
+    streams: u16,
+
+    /// Significant digits of the recorded latencies, percentiles are accurate to 10^-precision
+    #[arg(long, default_value_t = benchmark::DEFAULT_PRECISION, value_parser = clap::value_parser!(u8).range(1..=5))]
+    latency_precision: u8,
+
+    /// Load profile stage as "duration:from->to" or "duration:target", e.g. "30s:0->500"
+    /// then "5m:500" (can be repeated). Replaces --requests and --duration
+    #[arg(long = "stage", conflicts_with_all = ["duration", "rate"])]
+    stages: Vec<Stage>,
+
+    /// What the stage values control: active connections, or requests per second
+    /// dispatched to --connections workers
+    #[arg(long, value_enum, default_value_t = StageTargetArg::Connections)]
+    stage_target: StageTargetArg,
+
+    /// Length of the intervals live metrics are reported for
+    #[arg(long, value_parser = nonzero_duration, default_value = "1s")]
+    interval: Duration,
+
+    /// Print the rate, errors and latency percentiles of every interval as the run goes
+    #[arg(long, num_args = 0..=1, require_equals = true, default_value_t = false, default_missing_value = "true", action = ArgAction::Set)]
+    live: bool,
+
+    /// Save the metrics of every interval to a CSV file
+    #[arg(long)]
+    timeseries: Option<PathBuf>,
+
+    /// Stream every request to this file as newline-delimited JSON as it completes
+    #[arg(long)]
+    raw_log: Option<PathBuf>,
+
+    /// Also write the report with charts to this file as a single offline HTML page
+    #[arg(long)]
+    html: Option<PathBuf>,
+
+    /// Fail the run, with a non-zero exit code, unless a metric is within a limit,
+    /// e.g. "p99<250ms", "error_rate<1%", "rps>1000" or "200:p90<=100ms" for one
+    /// status (can be repeated)
+    #[arg(long = "threshold")]
+    thresholds: Vec<Threshold>,
+}
+
+impl Args {
+    fn target_uri(&self) -> &str {
+        self.target_uri
+            .as_deref()
+            .expect("Target is required without a plan")
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run the load test described by a TOML or YAML plan, flags override its values
+    Run(Box<RunArgs>),
+    /// Compare the report of a run against a baseline report and flag regressions
+    Compare(CompareArgs),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Table,
+    Markdown,
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StageTargetArg {
+    Connections,
+    Rate,
+}
+
+impl From<StageTargetArg> for StageTarget {
+    fn from(value: StageTargetArg) -> Self {
+        match value {
+            StageTargetArg::Connections => StageTarget::Connections,
+            StageTargetArg::Rate => StageTarget::Rate,
+        }
+    }
 }
 
 // THIS FUNCTIONS IS REFERENCED FROM AUTHOR
@@ -44,18 +259,48 @@ fn connection_in_range(s: &str) -> Result<u16, String> {
         ))
 }
 
+fn nonzero_duration(s: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(s) {
+        Ok(duration) if duration.is_zero() => Err(String::from("must be longer than zero")),
+        parsed => parsed.map_err(|e| e.to_string()),
+    }
+}
+
 struct Progress {
     bar: ProgressBar,
+    timed: bool,
+    completed: AtomicU64,
+    live: bool,
+    // Live lines go to stderr when the report on stdout is machine readable
+    live_to_stderr: bool,
 }
 
 impl BenchmarkStats for Progress {
     fn update(&self, n: u64) {
-        self.bar.inc(n);
+        if self.timed {
+            let completed = self.completed.fetch_add(n, Ordering::Relaxed) + n;
+            self.bar.set_message(format!("{} requests", completed));
+        } else {
+            self.bar.inc(n);
+        }
     }
 
     fn finish(&self) {
         self.bar.finish_and_clear();
     }
+
+    fn snapshot(&self, snapshot: &Snapshot) {
+        if self.live {
+            // Unlike println this also prints when the bar is hidden
+            self.bar.suspend(|| {
+                if self.live_to_stderr {
+                    eprintln!("{}", format_snapshot(snapshot))
+                } else {
+                    println!("{}", format_snapshot(snapshot))
+                }
+            });
+        }
+    }
 }
 
 impl Progress {
@@ -66,44 +311,289 @@ impl Progress {
                 .unwrap()
                 .progress_chars("##-"),
         );
-        Self { bar }
+        Self {
+            bar,
+            timed: false,
+            completed: AtomicU64::new(0),
+            live: false,
+            live_to_stderr: false,
+        }
+    }
+
+    // The bar counts seconds instead of requests,
+    // a ticker moves it forward since requests may complete in bursts
+    fn with_duration(duration: Duration) -> Self {
+        let len = duration.as_secs();
+        let bar = ProgressBar::new(len);
+        bar.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {bar} {pos:>7}/{len:7}s {msg}")
+                .unwrap()
+                .progress_chars("##-"),
+        );
+
+        let ticker = bar.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_millis(200));
+            while !ticker.is_finished() {
+                interval.tick().await;
+                ticker.set_position(ticker.elapsed().as_secs().min(len));
+            }
+        });
+
+        Self {
+            bar,
+            timed: true,
+            completed: AtomicU64::new(0),
+            live: false,
+            live_to_stderr: false,
+        }
+    }
+
+    fn with_live(mut self, live: bool, to_stderr: bool) -> Self {
+        self.live = live;
+        self.live_to_stderr = to_stderr;
+        self
     }
 }
 
 #[tokio::main]
 async fn main() {
-    let args = Args::parse();
-    let progress = Progress::new(args.requests.into());
-    println!("Start benchmarking {}", &args.target_uri);
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let args = match cli.command {
+        None => cli.args,
+        Some(Command::Run(run)) => {
+            let RunArgs { plan, args } = *run;
+            let matches = matches
+                .subcommand_matches("run")
+                .expect("Matched the run subcommand");
+            Plan::load(&plan)
+                .and_then(|plan| plan.apply(args, matches))
+                .unwrap_or_else(|msg| fail(msg))
+        }
+        Some(Command::Compare(compare)) => match compare::compare(&compare) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(msg) => fail(msg),
+        },
+    };
+    let endpoints = build_endpoints(&args).unwrap_or_else(|msg| fail(msg));
+    let scenario = build_scenario(&args).unwrap_or_else(|msg| fail(msg));
+    let feeders = build_feeders(&args).unwrap_or_else(|msg| fail(msg));
+    // Requests are logged under the name of their endpoint or step
+    let endpoint_names: Vec<String> = match &scenario {
+        Some(scenario) => scenario.steps().iter().map(|s| s.name.clone()).collect(),
+        None => endpoints.iter().map(|e| e.name.clone()).collect(),
+    };
+    let format = args.format.unwrap_or(match args.output_file {
+        Some(_) => OutputFormat::Csv,
+        None => OutputFormat::Table,
+    });
+    // Other lines go to stderr so they do not mix with a machine readable report
+    let machine_readable =
+        args.output_file.is_none() && matches!(format, OutputFormat::Csv | OutputFormat::Json);
+    let stages_duration = args.stages.iter().map(|s| s.duration).sum();
+    let progress = match args.duration {
+        _ if !args.stages.is_empty() => Progress::with_duration(stages_duration),
+        Some(duration) => Progress::with_duration(duration),
+        None => Progress::new(args.requests),
+    }
+    .with_live(args.live, machine_readable);
+    let say = |line: &str| {
+        if machine_readable {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+    let tls = TlsSettings {
+        ca_cert: args.ca_cert.clone(),
+        client_cert: args.client_cert.clone(),
+        client_key: args.client_key.clone(),
+        insecure: args.insecure,
+    };
+    if let Err(msg) = tls.validate() {
+        fail(format_args!("{:#}", msg));
+    }
+    let metadata = RunMetadata::from(&args);
+    let (events, raw_log) = match &args.raw_log {
+        Some(path) => match File::create(path) {
+            Ok(file) => {
+                let (tx, rx) = channel(RAW_LOG_BUFFER);
+                (
+                    Some(tx),
+                    Some(tokio::task::spawn_blocking(|| {
+                        write_raw_log(file, rx, endpoint_names)
+                    })),
+                )
+            }
+            Err(msg) => fail(format_args!("cannot create raw log: {}", msg)),
+        },
+        None => (None, None),
+    };
+    say(&format!("Start benchmarking {}", args.target_uri()));
     let result = benchmark::run(
         progress,
         BenchmarkSettings {
             connections: args.connections,
             requests: args.requests,
-            target_uri: benchmark::build_uri(&args.target_uri),
+            duration: args.duration,
+            rate: args.rate,
+            request_timeout: Some(args.timeout),
+            connect_timeout: Some(args.connect_timeout),
+            tls,
+            http2: args.http2,
+            streams: args.streams,
+            latency_precision: args.latency_precision,
+            stages: args.stages,
+            stage_target: args.stage_target.into(),
+            // Snapshots are only taken when something shows them
+            snapshot_interval: (args.live || args.timeseries.is_some() || args.html.is_some())
+                .then_some(args.interval),
+            events,
+            endpoints,
+            scenario,
+            feeders,
+            success_statuses: args.success_statuses,
         },
     )
     .await;
 
+    // The run dropped its end of the channel, the log is complete once flushed
+    if let Some(raw_log) = raw_log {
+        match raw_log.await {
+            Ok(Ok(())) => {}
+            Ok(Err(msg)) => say(&format!("error: cannot write raw log: {}", msg)),
+            Err(msg) => say(&format!("error: raw log writer failed: {}", msg)),
+        }
+    }
+
+    let passed;
     match result {
-        Err(msg) => println!("error: {:?}", msg),
+        Err(msg) => fail(format_args!("{:?}", msg)),
         Ok(summary) => {
-            let output = process_result(summary);
-            if let Some(file_path) = args.output_file {
-                let _ = write_csv(file_path, output);
-            } else {
-                println!("{}", Table::new(output).to_string())
+            let rates = summary
+                .intended_rate
+                .map(|intended| format_rates(intended, &summary));
+            let success_rate = format_success_rate(&summary);
+            let timeout_rate = format_timeout_rate(&summary);
+            let balance = format_connection_balance(&summary);
+            let tls_handshakes = format_tls_handshakes(&summary);
+            let protocols = format_protocols(&summary);
+            let stages = format_stages(&summary, args.stage_target);
+            if let Some(path) = &args.timeseries {
+                if let Err(msg) = write_timeseries(path, &summary.snapshots) {
+                    say(&format!("error: cannot write time series: {}", msg));
+                }
+            }
+            let metadata = metadata.finished(&summary);
+            let totals = total_statistics(&summary);
+            let output = process_result(&summary);
+            let iterations = iteration_statistics(&summary);
+            let checks = check_statistics(&summary);
+            let statuses = status_statistics(&summary);
+            let verdicts: Vec<_> = args
+                .thresholds
+                .iter()
+                .map(|threshold| {
+                    let verdict = threshold.check(&statuses, &totals);
+                    (threshold.format_verdict(&verdict), verdict.passed)
+                })
+                .collect();
+            passed = verdicts.iter().all(|(_, passed)| *passed);
+            if let Some(path) = &args.html {
+                let all = merged_outcomes(&summary);
+                let page = html::render(&metadata, &output, &summary.snapshots, &all);
+                if let Err(msg) = std::fs::write(path, page) {
+                    say(&format!("error: cannot write HTML report: {}", msg));
+                }
+            }
+            let report = match format {
+                OutputFormat::Table => Ok(Table::new(&output).to_string()),
+                OutputFormat::Markdown => {
+                    Ok(Table::new(&output).with(Style::markdown()).to_string())
+                }
+                OutputFormat::Csv => csv_report(&output),
+                OutputFormat::Json => json_report(metadata, totals, output, iterations, checks),
+            };
+            let written = report.and_then(|report| match &args.output_file {
+                Some(path) => std::fs::write(path, report).map_err(Into::into),
+                None => {
+                    println!("{}", report);
+                    Ok(())
+                }
+            });
+            if let Err(msg) = written {
+                say(&format!("error: cannot write report: {}", msg));
+            }
+            for stage in stages {
+                say(&stage);
+            }
+            if let Some(iterations) = format_iterations(&summary) {
+                say(&iterations);
+            }
+            if let Some(checks) = format_checks(&summary) {
+                say(&checks);
+            }
+            say(&success_rate);
+            say(&timeout_rate);
+            say(&balance);
+            say(&protocols);
+            if let Some(tls_handshakes) = tls_handshakes {
+                say(&tls_handshakes);
+            }
+            if let Some(rates) = rates {
+                say(&rates);
+            }
+            for (verdict, _) in verdicts {
+                say(&verdict);
             }
         }
     }
+    if !passed && !args.thresholds.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+// Ends a run that cannot go on, the error goes to stderr
+// so it never mixes with a report on stdout
+fn fail(msg: impl Display) -> ! {
+    eprintln!("error: {}", msg);
+    std::process::exit(1);
+}
+
+fn build_request(
+    uri: &str,
+    method: &str,
+    headers: &[String],
+    body: Option<&str>,
+) -> Result<RequestTemplate, Box<dyn Error>> {
+    let mut request = RequestTemplate::get(uri)?.with_method(method)?;
+    for header in headers {
+        request = request.with_header(header)?;
+    }
+    if let Some(body) = body {
+        let body = match body.strip_prefix('@') {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => body.to_string(),
+        };
+        request = request.with_body(&body)?;
+    }
+    Ok(request)
 }
 
 #[derive(Debug, Tabled, Serialize)]
 struct StatusStatistics {
-    status: u16,
-    requests: usize,
+    endpoint: String,
+    // A status code, or the kind of error for requests that got no response
+    status: String,
+    requests: u64,
+    // Share of all requests in percent
     #[tabled(display_with = "format_float")]
-    average_rate: f64,
+    percent: f64,
+    // Requests per second over the whole run, n/a when no time passed
+    #[tabled(display_with = "format_optional_float")]
+    average_rate: Option<f64>,
     #[tabled(display_with = "format_float")]
     min: f64,
     #[tabled(display_with = "format_float")]
@@ -116,96 +606,617 @@ struct StatusStatistics {
     p90: f64,
     #[tabled(display_with = "format_float")]
     p99: f64,
+    // Percentiles corrected for coordinated omission: measured from the intended
+    // send time with a rate, and with the requests a slow response held back otherwise
+    #[tabled(display_with = "format_float")]
+    p90_corrected: f64,
+    #[tabled(display_with = "format_float")]
+    p99_corrected: f64,
+    // Response body bytes per second over the whole run,
+    // n/a when no time passed or no request got the outcome
+    #[tabled(display_with = "format_optional_float")]
+    throughput: Option<f64>,
+    #[tabled(display_with = "format_optional_float")]
+    mean_body_size: Option<f64>,
+    // Requests of the row that timed out and their percent of its requests,
+    // all of them on the timeout row of an endpoint and none on the others
+    timeouts: u64,
+    #[tabled(display_with = "format_float")]
+    timeout_rate: f64,
 }
 
 fn format_float(num: &f64) -> String {
     format!("{:.2}", num)
 }
 
-fn process_result(summary: BenchmarkResult) -> Vec<StatusStatistics> {
-    let mut status_latencies: HashMap<u16, Vec<f64>> = HashMap::new();
-    for req_sum in summary.request_summaries {
-        let latency = req_sum.latency.as_micros() as f64 / 1000_f64;
-        if let Some(status_statistic) = status_latencies.get_mut(&req_sum.status_code) {
-            status_statistic.push(latency);
-        } else {
-            status_latencies.insert(req_sum.status_code, vec![latency]);
-        }
+fn format_optional_float(num: &Option<f64>) -> String {
+    match num {
+        Some(num) => format_float(num),
+        None => String::from("n/a"),
+    }
+}
+
+fn format_rates(intended: u32, summary: &BenchmarkResult) -> String {
+    let achieved = per_second(summary.total_requests(), summary.total_time);
+    format!(
+        "intended rate: {} req/s, achieved rate: {} req/s, late requests: {}",
+        intended,
+        format_optional_float(&achieved),
+        summary.late_requests
+    )
+}
+
+fn format_success_rate(summary: &BenchmarkResult) -> String {
+    format!(
+        "success rate: {}% ({} of {} requests answered {}), error rate: {}%",
+        format_float(&percent_of(
+            summary.success_requests(),
+            summary.total_requests()
+        )),
+        summary.success_requests(),
+        summary.total_requests(),
+        summary.success_statuses,
+        format_float(&summary.error_rate())
+    )
+}
+
+// Requests without a response before the timeout
+fn timeout_requests(outcomes: &HashMap<Outcome, OutcomeStats>) -> u64 {
+    outcomes
+        .get(&Outcome::Error(RequestError::Timeout))
+        .map_or(0, OutcomeStats::requests)
+}
+
+fn format_timeout_rate(summary: &BenchmarkResult) -> String {
+    let timeouts = timeout_requests(&summary.outcomes);
+    format!(
+        "timeout rate: {}% ({} of {} requests)",
+        format_float(&percent_of(timeouts, summary.total_requests())),
+        timeouts,
+        summary.total_requests()
+    )
+}
+
+fn format_connection_balance(summary: &BenchmarkResult) -> String {
+    let counts = &summary.connection_requests;
+    let min = counts.iter().min().copied().unwrap_or_default();
+    let max = counts.iter().max().copied().unwrap_or_default();
+    let mean = counts.iter().sum::<u64>() as f64 / counts.len().max(1) as f64;
+    format!(
+        "requests per connection: min {}, max {}, mean {}",
+        min,
+        max,
+        format_float(&mean)
+    )
+}
+
+fn format_protocols(summary: &BenchmarkResult) -> String {
+    let counts: Vec<String> = summary
+        .protocols
+        .iter()
+        .map(|(protocol, count)| format!("{} {}", protocol, count))
+        .collect();
+    format!("protocols: {}", counts.join(", "))
+}
+
+// Durations are in milliseconds
+#[derive(Debug, Serialize)]
+struct TlsHandshakes {
+    handshakes: usize,
+    mean: f64,
+    p99: f64,
+}
+
+// None without a https target
+fn tls_handshake_statistics(summary: &BenchmarkResult) -> Option<TlsHandshakes> {
+    if summary.tls_handshakes.is_empty() {
+        return None;
+    }
+    let handshakes: Vec<f64> = summary
+        .tls_handshakes
+        .iter()
+        .map(|d| as_millis(*d))
+        .collect();
+    let mut data = statrs::statistics::Data::new(handshakes.clone());
+    Some(TlsHandshakes {
+        handshakes: handshakes.len(),
+        mean: handshakes.as_slice().mean(),
+        p99: data.percentile(99),
+    })
+}
+
+fn format_tls_handshakes(summary: &BenchmarkResult) -> Option<String> {
+    let statistics = tls_handshake_statistics(summary)?;
+    Some(format!(
+        "tls handshakes: {}, mean {} ms, p99 {} ms",
+        statistics.handshakes,
+        format_float(&statistics.mean),
+        format_float(&statistics.p99)
+    ))
+}
+
+fn percent_of(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0_f64;
     }
+    part as f64 * 100_f64 / total as f64
+}
+
+// `None` when no time passed to divide by
+fn per_second(count: u64, time: Duration) -> Option<f64> {
+    (time.as_micros() > 0).then(|| count as f64 * 1_000_000_f64 / time.as_micros() as f64)
+}
+
+fn as_millis(duration: Duration) -> f64 {
+    duration.as_micros() as f64 / 1000_f64
+}
+
+// Histograms record microseconds, the report shows milliseconds
+fn micros_as_millis(micros: f64) -> f64 {
+    micros / 1000_f64
+}
+
+// Statistics by endpoint then status, shares are of every request of the run
+fn process_result(summary: &BenchmarkResult) -> Vec<StatusStatistics> {
+    let total_requests = summary.total_requests();
+    summary
+        .endpoints
+        .iter()
+        .flat_map(|endpoint| {
+            outcome_statistics(
+                &endpoint.name,
+                &endpoint.outcomes,
+                total_requests,
+                summary.total_time,
+            )
+        })
+        .collect()
+}
+
+// The statistics of every status over all endpoints together,
+// which is what thresholds on a status are checked against
+fn status_statistics(summary: &BenchmarkResult) -> Vec<StatusStatistics> {
+    outcome_statistics(
+        "all",
+        &summary.outcomes,
+        summary.total_requests(),
+        summary.total_time,
+    )
+}
+
+fn outcome_statistics(
+    endpoint: &str,
+    outcomes: &HashMap<Outcome, OutcomeStats>,
+    total_requests: u64,
+    total_time: Duration,
+) -> Vec<StatusStatistics> {
+    let mut statistics: Vec<StatusStatistics> = outcomes
+        .iter()
+        .map(|(outcome, stats)| {
+            let timeouts = match outcome {
+                Outcome::Error(RequestError::Timeout) => stats.requests(),
+                _ => 0,
+            };
+            StatusStatistics {
+                timeouts,
+                timeout_rate: percent_of(timeouts, stats.requests()),
+                ..calculate_statistic(
+                    endpoint,
+                    outcome.to_string(),
+                    stats,
+                    total_requests,
+                    total_time,
+                )
+            }
+        })
+        .collect();
+    statistics.sort_by(|a, b| a.status.cmp(&b.status));
+    statistics
+}
+
+// A title and a table for every stage of the load profile
+fn format_stages(summary: &BenchmarkResult, target: StageTargetArg) -> Vec<String> {
+    let unit = match target {
+        StageTargetArg::Connections => "connections",
+        StageTargetArg::Rate => "req/s",
+    };
+    summary
+        .stages
+        .iter()
+        .enumerate()
+        .map(|(i, stage)| {
+            format!(
+                "stage {} ({} {})\n{}",
+                i + 1,
+                stage.stage,
+                unit,
+                Table::new(outcome_statistics(
+                    "all",
+                    &stage.outcomes,
+                    stage.outcomes.values().map(OutcomeStats::requests).sum(),
+                    stage.stage.duration,
+                ))
+            )
+        })
+        .collect()
+}
+
+// How the iterations of the scenario ended, empty without one.
+// Shares are of every iteration
+fn iteration_statistics(summary: &BenchmarkResult) -> Vec<StatusStatistics> {
+    let total = summary
+        .iterations
+        .values()
+        .map(OutcomeStats::requests)
+        .sum();
+    let mut statistics: Vec<StatusStatistics> = summary
+        .iterations
+        .iter()
+        .map(|(outcome, stats)| {
+            let status = match outcome {
+                IterationOutcome::Completed => String::from("completed"),
+                IterationOutcome::Failed(step) => {
+                    format!("failed at {}", summary.endpoints[*step].name)
+                }
+            };
+            calculate_statistic("iteration", status, stats, total, summary.total_time)
+        })
+        .collect();
+    statistics.sort_by(|a, b| a.status.cmp(&b.status));
+    statistics
+}
 
-    let mut statistics: Vec<StatusStatistics> = vec![];
-    for (key, val) in status_latencies.iter() {
-        statistics.push(calculate_statistic(key, val, summary.total_time));
+fn format_iterations(summary: &BenchmarkResult) -> Option<String> {
+    let statistics = iteration_statistics(summary);
+    if statistics.is_empty() {
+        return None;
     }
+    Some(format!("iterations\n{}", Table::new(statistics)))
+}
 
+#[derive(Debug, Tabled, Serialize)]
+struct CheckStatistics {
+    check: String,
+    passed: u64,
+    failed: u64,
+    // Percent of the responses the check ran on
+    #[tabled(display_with = "format_float")]
+    pass_rate: f64,
+}
+
+// Pass rate of every check by name, empty without checks
+fn check_statistics(summary: &BenchmarkResult) -> Vec<CheckStatistics> {
+    let mut statistics: Vec<CheckStatistics> = summary
+        .checks
+        .iter()
+        .map(|(name, count)| CheckStatistics {
+            check: name.clone(),
+            passed: count.passed,
+            failed: count.failed,
+            pass_rate: percent_of(count.passed, count.passed + count.failed),
+        })
+        .collect();
+    statistics.sort_by(|a, b| a.check.cmp(&b.check));
     statistics
 }
 
+fn format_checks(summary: &BenchmarkResult) -> Option<String> {
+    let statistics = check_statistics(summary);
+    if statistics.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "checks ({} responses failed at least one)\n{}",
+        summary.failed_checks,
+        Table::new(statistics)
+    ))
+}
+
 fn calculate_statistic(
-    status: &u16,
-    latencies: &Vec<f64>,
+    endpoint: &str,
+    status: String,
+    stats: &OutcomeStats,
+    total_requests: u64,
     total_time: Duration,
 ) -> StatusStatistics {
-    let variance = latencies.variance();
-    let mut data = statrs::statistics::Data::new(latencies.clone());
+    let latency = &stats.latency;
+    let corrected = &stats.corrected_latency;
+    let requests = stats.requests();
     StatusStatistics {
-        status: *status,
-        requests: latencies.len(),
-        average_rate: latencies.len() as f64 * 1_000_000_f64 / total_time.as_micros() as f64,
-        min: latencies.min(),
-        max: latencies.max(),
-        mean: latencies.mean(),
-        std: variance.sqrt(),
-        p90: data.percentile(90),
-        p99: data.percentile(99),
+        endpoint: endpoint.to_string(),
+        status,
+        requests,
+        percent: percent_of(requests, total_requests),
+        average_rate: per_second(requests, total_time),
+        min: micros_as_millis(latency.min() as f64),
+        max: micros_as_millis(latency.max() as f64),
+        mean: micros_as_millis(latency.mean()),
+        std: micros_as_millis(latency.stdev()),
+        p90: micros_as_millis(latency.value_at_quantile(0.9) as f64),
+        p99: micros_as_millis(latency.value_at_quantile(0.99) as f64),
+        p90_corrected: micros_as_millis(corrected.value_at_quantile(0.9) as f64),
+        p99_corrected: micros_as_millis(corrected.value_at_quantile(0.99) as f64),
+        throughput: per_second(stats.body_bytes, total_time),
+        mean_body_size: (requests > 0).then(|| stats.body_bytes as f64 / requests as f64),
+        // Filled in by the callers that know the outcome,
+        // an iteration ends with a failed step rather than a timeout
+        timeouts: 0,
+        timeout_rate: 0_f64,
+    }
+}
+
+// Requests the raw log can fall behind by before it slows the run down
+const RAW_LOG_BUFFER: usize = 8192;
+
+// One line of the raw log, durations are in milliseconds
+#[derive(Serialize, Deserialize)]
+struct RawEvent {
+    // RFC 3339 wall clock time the request was sent at
+    timestamp: String,
+    connection: u16,
+    // Missing from logs written before endpoints existed
+    #[serde(default)]
+    endpoint: String,
+    // The status code, or the kind of error for requests that got no response
+    outcome: String,
+    status: Option<u16>,
+    latency: f64,
+    corrected_latency: f64,
+    time_to_first_byte: f64,
+    body_bytes: u64,
+}
+
+impl RawEvent {
+    fn from(event: &RequestEvent, endpoints: &[String]) -> Self {
+        let summary = &event.summary;
+        Self {
+            timestamp: humantime::format_rfc3339_micros(event.timestamp).to_string(),
+            connection: event.connection,
+            endpoint: endpoints[event.endpoint].clone(),
+            outcome: summary.outcome.to_string(),
+            status: match summary.outcome {
+                Outcome::Status(status) => Some(status),
+                Outcome::Error(_) => None,
+            },
+            latency: as_millis(summary.latency),
+            corrected_latency: as_millis(summary.corrected_latency()),
+            time_to_first_byte: as_millis(summary.time_to_first_byte),
+            body_bytes: summary.body_bytes,
+        }
     }
 }
 
-fn write_csv(path: String, records: Vec<StatusStatistics>) -> Result<(), Box<dyn Error>> {
-    // Open a file to write the CSV output
-    let file = File::create(path)?;
-
-    // Create a CSV writer
-    let mut writer = Writer::from_writer(file);
-
-    // Write the header row
-    writer.write_record(&[
-        "status",
-        "requests",
-        "average_rate",
-        "min",
-        "max",
-        "mean",
-        "std",
-        "p90",
-        "p99",
-    ])?;
-    for x in records.iter() {
-        writer.write_record(&[
-            &x.status.to_string(),
-            &x.requests.to_string(),
-            &x.min.to_string(),
-            &x.max.to_string(),
-            &x.mean.to_string(),
-            &x.std.to_string(),
-            &x.p90.to_string(),
-            &x.p99.to_string(),
-        ])?;
-    }
-
-    // Flush the CSV writer to ensure all data is written
+// Runs on a blocking thread until the benchmark drops the sending end
+fn write_raw_log(
+    file: File,
+    mut events: Receiver<RequestEvent>,
+    endpoints: Vec<String>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut writer = BufWriter::new(file);
+    while let Some(event) = events.blocking_recv() {
+        serde_json::to_writer(&mut writer, &RawEvent::from(&event, &endpoints))?;
+        writer.write_all(b"\n")?;
+    }
     writer.flush()?;
+    Ok(())
+}
+
+fn format_snapshot(snapshot: &Snapshot) -> String {
+    format!(
+        "[{:>6.1}s] {} req/s, {} errors, p50 {} ms, p90 {} ms, p99 {} ms{}",
+        snapshot.elapsed.as_secs_f64(),
+        format_float(&snapshot.rps()),
+        snapshot.errors,
+        format_float(&as_millis(snapshot.p50)),
+        format_float(&as_millis(snapshot.p90)),
+        format_float(&as_millis(snapshot.p99)),
+        if snapshot.partial { " (partial)" } else { "" }
+    )
+}
+
+#[derive(Serialize)]
+struct SnapshotRow {
+    elapsed: f64,
+    requests: u64,
+    rps: f64,
+    errors: u64,
+    p50: f64,
+    p90: f64,
+    p99: f64,
+    // The last interval, cut short by the end of the run
+    partial: bool,
+}
 
+fn write_timeseries(path: &Path, snapshots: &[Snapshot]) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_path(path)?;
+    for snapshot in snapshots {
+        writer.serialize(SnapshotRow {
+            elapsed: snapshot.elapsed.as_secs_f64(),
+            requests: snapshot.requests,
+            rps: snapshot.rps(),
+            errors: snapshot.errors,
+            p50: as_millis(snapshot.p50),
+            p90: as_millis(snapshot.p90),
+            p99: as_millis(snapshot.p99),
+            partial: snapshot.partial,
+        })?;
+    }
+    writer.flush()?;
     Ok(())
 }
 
+fn csv_report(records: &[StatusStatistics]) -> Result<String, Box<dyn Error>> {
+    let mut writer = Writer::from_writer(vec![]);
+    for record in records {
+        writer.serialize(record)?;
+    }
+    Ok(String::from_utf8(writer.into_inner()?)?)
+}
+
+#[derive(Debug, Serialize)]
+struct RunMetadata {
+    target: String,
+    method: String,
+    // RFC 3339, only known once the run is done
+    start_time: Option<String>,
+    // Seconds
+    total_time: Option<f64>,
+    // Responses per HTTP version the server actually answered with,
+    // also only known once the run is done
+    protocols: BTreeMap<String, u64>,
+    settings: RunSettings,
+}
+
+// Durations are in seconds
+#[derive(Debug, Serialize)]
+struct RunSettings {
+    connections: u16,
+    // Not set when the run is bounded by a duration or stages instead
+    requests: Option<u64>,
+    duration: Option<f64>,
+    rate: Option<u32>,
+    timeout: f64,
+    connect_timeout: f64,
+    http2: bool,
+    streams: u16,
+    stages: Vec<String>,
+    stage_target: StageTargetArg,
+    success_status: String,
+}
+
+impl RunMetadata {
+    fn from(args: &Args) -> Self {
+        let bounded = args.duration.is_some() || !args.stages.is_empty();
+        Self {
+            target: args.target_uri().to_string(),
+            method: args.method.to_uppercase(),
+            start_time: None,
+            total_time: None,
+            protocols: BTreeMap::new(),
+            settings: RunSettings {
+                connections: args.connections,
+                requests: (!bounded).then_some(args.requests),
+                duration: args.duration.map(|d| d.as_secs_f64()),
+                rate: args.rate,
+                timeout: args.timeout.as_secs_f64(),
+                connect_timeout: args.connect_timeout.as_secs_f64(),
+                http2: args.http2,
+                streams: args.streams,
+                stages: args.stages.iter().map(Stage::to_string).collect(),
+                stage_target: args.stage_target,
+                success_status: args.success_statuses.to_string(),
+            },
+        }
+    }
+
+    fn finished(mut self, summary: &BenchmarkResult) -> Self {
+        self.start_time = Some(humantime::format_rfc3339_millis(summary.started_at).to_string());
+        self.total_time = Some(summary.total_time.as_secs_f64());
+        self.protocols = summary.protocols.clone();
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct Totals {
+    // Statistics over every request whatever its outcome
+    #[serde(flatten)]
+    statistics: StatusStatistics,
+    successes: u64,
+    errors: u64,
+    // Percent of all requests
+    error_rate: f64,
+    // Responses that failed a check, they are not counted as errors
+    failed_checks: u64,
+    late_requests: u64,
+    // Requests sent by each connection, to make load imbalance visible
+    connection_requests: Vec<u64>,
+    // Null without a https target
+    tls_handshakes: Option<TlsHandshakes>,
+}
+
+// Every request of the run whatever its outcome
+fn merged_outcomes(summary: &BenchmarkResult) -> OutcomeStats {
+    let mut outcomes = summary.outcomes.values();
+    let mut all = outcomes
+        .next()
+        .cloned()
+        .unwrap_or_else(|| OutcomeStats::new(benchmark::DEFAULT_PRECISION));
+    for stats in outcomes {
+        all.add(stats);
+    }
+    all
+}
+
+fn total_statistics(summary: &BenchmarkResult) -> Totals {
+    let all = merged_outcomes(summary);
+    let successes = summary.success_requests();
+    let errors = all.requests() - successes;
+    let timeouts = timeout_requests(&summary.outcomes);
+    Totals {
+        statistics: StatusStatistics {
+            timeouts,
+            timeout_rate: percent_of(timeouts, all.requests()),
+            ..calculate_statistic(
+                "all",
+                String::from("all"),
+                &all,
+                all.requests(),
+                summary.total_time,
+            )
+        },
+        successes,
+        errors,
+        error_rate: summary.error_rate(),
+        failed_checks: summary.failed_checks,
+        late_requests: summary.late_requests,
+        connection_requests: summary.connection_requests.clone(),
+        tls_handshakes: tls_handshake_statistics(summary),
+    }
+}
+
+#[derive(Serialize)]
+struct JsonReport {
+    metadata: RunMetadata,
+    totals: Totals,
+    statuses: Vec<StatusStatistics>,
+    // Only with a scenario, its steps are reported like endpoints in the statuses
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    iterations: Vec<StatusStatistics>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    checks: Vec<CheckStatistics>,
+}
+
+fn json_report(
+    metadata: RunMetadata,
+    totals: Totals,
+    statuses: Vec<StatusStatistics>,
+    iterations: Vec<StatusStatistics>,
+    checks: Vec<CheckStatistics>,
+) -> Result<String, Box<dyn Error>> {
+    let report = JsonReport {
+        metadata,
+        totals,
+        statuses,
+        iterations,
+        checks,
+    };
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
 #[cfg(test)]
 mod test {
+    use benchmark::{CheckCount, EndpointSummary};
+
     use super::*;
 
+    fn parse_args<const N: usize>(args: [&str; N]) -> Result<Args, clap::Error> {
+        Cli::try_parse_from(args).map(|cli| cli.args)
+    }
+
     #[test]
     fn test_works_with_default_argument() {
-        let args = Args::try_parse_from([
+        let args = parse_args([
             "cli_load_test",
             "-t",
             "http://localhost:8080/person",
@@ -215,14 +1226,88 @@ mod test {
         .unwrap();
         assert_eq!(args.connections, 512);
         assert_eq!(args.requests, 100_000);
-        assert_eq!(args.target_uri, "http://localhost:8080/person");
+        assert_eq!(args.target_uri(), "http://localhost:8080/person");
         assert_eq!(args.output_file, Some(String::from("test.text")));
+        assert_eq!(args.duration, None);
+        assert_eq!(args.success_statuses.to_string(), "2xx");
+        assert_eq!(args.rate, None);
+        assert_eq!(args.timeout, Duration::from_secs(30));
+        assert_eq!(args.connect_timeout, Duration::from_secs(10));
+        assert_eq!(args.method, "GET");
+        assert!(args.headers.is_empty());
+        assert_eq!(args.body, None);
+        assert!(!args.insecure);
     }
 
     #[test]
-    fn test_out_file_must_be_provided() {
-        let result = Args::try_parse_from(["cli_load_test", "-t", "http://localhost:8080/person"]);
+    fn test_streams_require_http2() {
+        let result = parse_args(["cli_load_test", "-t", "http://localhost", "--streams", "8"]);
         assert!(result.is_err());
+
+        let args = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost",
+            "--http2",
+            "--streams",
+            "8",
+        ])
+        .unwrap();
+        assert!(args.http2);
+        assert_eq!(args.streams, 8);
+    }
+
+    #[test]
+    fn test_interval_must_not_be_zero() {
+        let result = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost",
+            "--interval",
+            "0s",
+        ]);
+        assert!(result.is_err());
+
+        let args = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost",
+            "--interval",
+            "500ms",
+        ])
+        .unwrap();
+        assert_eq!(args.interval, Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_duration_conflicts_with_requests() {
+        let args = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost:8080/person",
+            "-d",
+            "5m",
+        ])
+        .unwrap();
+        assert_eq!(args.duration, Some(Duration::from_secs(300)));
+
+        let result = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost:8080/person",
+            "-d",
+            "5m",
+            "-r",
+            "1000",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_out_file_is_optional() {
+        // Without one the report is printed
+        let args = parse_args(["cli_load_test", "-t", "http://localhost:8080/person"]).unwrap();
+        assert_eq!(args.output_file, None);
     }
 
     #[test]
@@ -237,22 +1322,291 @@ mod test {
             "placeholder",
         ];
 
-        assert!(Args::try_parse_from({
+        assert!(parse_args({
             a[6] = "0";
             a
         })
         .is_err());
 
-        assert!(Args::try_parse_from({
+        assert!(parse_args({
             a[6] = "-1";
             a
         })
         .is_err());
 
-        assert!(Args::try_parse_from({
+        assert!(parse_args({
             a[6] = "65527"; // > 65536 - 10
             a
         })
         .is_err());
     }
+
+    #[test]
+    fn test_process_result_reports_corrected_percentiles() {
+        let mut summary = BenchmarkResult::new(benchmark::build_uri("http://localhost:8080"));
+        summary.total_time = Duration::from_secs(1);
+        let start = std::time::Instant::now();
+        let mut stats = OutcomeStats::new(benchmark::DEFAULT_PRECISION);
+        for i in 0..100 {
+            // Every request waited 100ms for a worker before it was sent
+            stats.record(&benchmark::RequestSummary {
+                intended_at: start,
+                started_at: start + Duration::from_millis(100),
+                expected_interval: None,
+                latency: Duration::from_millis(i),
+                time_to_first_byte: Duration::from_millis(i / 2),
+                body_bytes: 100,
+                outcome: Outcome::Status(200),
+            });
+        }
+        summary.outcomes.insert(Outcome::Status(200), stats);
+        summary.endpoints.push(EndpointSummary {
+            name: String::from("GET /"),
+            outcomes: summary.outcomes.clone(),
+        });
+
+        let statistics = process_result(&summary);
+        assert_eq!(statistics.len(), 1);
+        assert_eq!(statistics[0].requests, 100);
+        assert!(statistics[0].p99 < 100_f64);
+        assert!(statistics[0].p99_corrected >= 100_f64 + statistics[0].p99 - 1_f64);
+        assert_eq!(statistics[0].throughput, Some(10_000_f64));
+        assert_eq!(statistics[0].mean_body_size, Some(100_f64));
+
+        // A run without requests has no body sizes to average
+        let empty = BenchmarkResult::new(benchmark::build_uri("http://localhost:8080"));
+        let totals = total_statistics(&empty);
+        assert_eq!(totals.statistics.throughput, None);
+        assert_eq!(totals.statistics.average_rate, None);
+        assert_eq!(totals.statistics.mean_body_size, None);
+    }
+
+    #[test]
+    fn test_stages() {
+        let args = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost",
+            "--stage",
+            "30s:0->500",
+            "--stage",
+            "5m:500",
+            "--stage-target",
+            "rate",
+        ])
+        .unwrap();
+        assert_eq!(args.stages.len(), 2);
+        assert_eq!(args.stages[1].to_string(), "5m:500");
+        assert_eq!(args.stage_target, StageTargetArg::Rate);
+
+        let result = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost",
+            "--stage",
+            "30s:0->500",
+            "-d",
+            "30s",
+        ]);
+        assert!(result.is_err());
+    }
+
+    fn summary_with_requests() -> BenchmarkResult {
+        let mut summary = BenchmarkResult::new(benchmark::build_uri("http://localhost:8080"));
+        summary.total_time = Duration::from_secs(1);
+        let start = std::time::Instant::now();
+        for (endpoint, outcome, count) in [
+            ("read", Outcome::Status(200), 90),
+            ("write", Outcome::Error(RequestError::Timeout), 10),
+        ] {
+            let mut stats = OutcomeStats::new(benchmark::DEFAULT_PRECISION);
+            for _ in 0..count {
+                stats.record(&benchmark::RequestSummary {
+                    intended_at: start,
+                    started_at: start,
+                    expected_interval: None,
+                    latency: Duration::from_millis(10),
+                    time_to_first_byte: Duration::from_millis(5),
+                    body_bytes: 0,
+                    outcome,
+                });
+            }
+            summary.outcomes.insert(outcome, stats.clone());
+            summary.endpoints.push(EndpointSummary {
+                name: endpoint.to_string(),
+                outcomes: HashMap::from([(outcome, stats)]),
+            });
+        }
+        summary
+    }
+
+    #[test]
+    fn test_process_result_groups_by_endpoint() {
+        let statistics = process_result(&summary_with_requests());
+        let rows: Vec<_> = statistics
+            .iter()
+            .map(|s| (s.endpoint.as_str(), s.status.as_str(), s.percent))
+            .collect();
+        assert_eq!(
+            rows,
+            [("read", "200", 90_f64), ("write", "timeout", 10_f64)]
+        );
+        // Timeouts are counted per row
+        assert_eq!(statistics[0].timeouts, 0);
+        assert_eq!(statistics[0].timeout_rate, 0_f64);
+        assert_eq!(statistics[1].timeouts, 10);
+        assert_eq!(statistics[1].timeout_rate, 100_f64);
+
+        // Even next to the other outcomes of the endpoint
+        let mut summary = summary_with_requests();
+        let ok = summary.endpoints[0].outcomes[&Outcome::Status(200)].clone();
+        summary.endpoints[1]
+            .outcomes
+            .insert(Outcome::Status(200), ok);
+        let write: Vec<_> = process_result(&summary)[1..]
+            .iter()
+            .map(|s| (s.status.clone(), s.timeouts, s.timeout_rate))
+            .collect();
+        assert_eq!(
+            write,
+            [
+                (String::from("200"), 0, 0_f64),
+                (String::from("timeout"), 10, 100_f64)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_report_columns_match_header() {
+        let report = csv_report(&process_result(&summary_with_requests())).unwrap();
+        let mut reader = csv::Reader::from_reader(report.as_bytes());
+        let header = reader.headers().unwrap().clone();
+        let average_rate = header.iter().position(|h| h == "average_rate").unwrap();
+        for row in reader.records() {
+            let row = row.unwrap();
+            assert_eq!(row.len(), header.len());
+            let rate: f64 = row[average_rate].parse().unwrap();
+            assert!(rate == 90_f64 || rate == 10_f64);
+        }
+    }
+
+    #[test]
+    fn test_json_report_has_metadata_and_totals() {
+        let args =
+            parse_args(["cli_load_test", "-t", "http://localhost:8080", "-d", "1s"]).unwrap();
+        let mut summary = summary_with_requests();
+        summary.checks.insert(
+            String::from("fast"),
+            CheckCount {
+                passed: 72,
+                failed: 18,
+            },
+        );
+        summary.failed_checks = 18;
+        summary.connection_requests = vec![60, 40];
+        summary.tls_handshakes = vec![Duration::from_millis(10), Duration::from_millis(20)];
+        summary.protocols = BTreeMap::from([(String::from("HTTP/2.0"), 100)]);
+        let metadata = RunMetadata::from(&args).finished(&summary);
+        let totals = total_statistics(&summary);
+        let checks = check_statistics(&summary);
+        let report =
+            json_report(metadata, totals, process_result(&summary), vec![], checks).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&report).unwrap();
+        assert_eq!(json["metadata"]["target"], "http://localhost:8080");
+        assert_eq!(json["metadata"]["settings"]["duration"], 1.0);
+        assert!(json["metadata"]["settings"]["requests"].is_null());
+        assert_eq!(json["metadata"]["protocols"]["HTTP/2.0"], 100);
+        assert_eq!(json["totals"]["requests"], 100);
+        assert_eq!(json["totals"]["errors"], 10);
+        assert_eq!(json["totals"]["error_rate"], 10.0);
+        assert_eq!(json["totals"]["timeouts"], 10);
+        assert_eq!(json["totals"]["timeout_rate"], 10.0);
+        assert_eq!(json["statuses"].as_array().unwrap().len(), 2);
+        assert_eq!(json["totals"]["failed_checks"], 18);
+        assert_eq!(
+            json["totals"]["connection_requests"],
+            serde_json::json!([60, 40])
+        );
+        assert_eq!(json["totals"]["tls_handshakes"]["handshakes"], 2);
+        assert_eq!(json["totals"]["tls_handshakes"]["mean"], 15.0);
+        assert_eq!(json["checks"][0]["check"], "fast");
+        assert_eq!(json["checks"][0]["pass_rate"], 80.0);
+    }
+
+    #[test]
+    fn test_compare_needs_no_target() {
+        let cli = Cli::try_parse_from(["cli_load_test", "compare", "base.csv", "new.csv"]).unwrap();
+        assert!(matches!(cli.command, Some(Command::Compare(_))));
+        assert!(cli.args.target_uri.is_none());
+    }
+
+    #[test]
+    fn test_thresholds_check_totals_and_statuses() {
+        let summary = summary_with_requests();
+        let statuses = status_statistics(&summary);
+        let totals = total_statistics(&summary);
+        // A status is checked over every endpoint
+        assert!(statuses.iter().all(|s| s.endpoint == "all"));
+        let check = |threshold: &str| {
+            threshold
+                .parse::<Threshold>()
+                .unwrap()
+                .check(&statuses, &totals)
+        };
+
+        assert!(check("p99<20ms").passed);
+        assert!(!check("p99<5ms").passed);
+        assert!(check("error_rate<=10%").passed);
+        assert!(!check("error_rate<1%").passed);
+        assert!(check("rps>99").passed);
+        assert!(check("200:requests>=90").passed);
+        assert!(check("500:rps<1").passed);
+        // Nothing to measure for a status no request got
+        assert_eq!(check("500:p99<1s").actual, None);
+        assert!(!check("500:p99<1s").passed);
+    }
+
+    #[test]
+    fn test_html_report_is_self_contained() {
+        let args = parse_args(["cli_load_test", "-t", "http://localhost:8080"]).unwrap();
+        let summary = summary_with_requests();
+        let metadata = RunMetadata::from(&args).finished(&summary);
+        let page = html::render(
+            &metadata,
+            &process_result(&summary),
+            &summary.snapshots,
+            &merged_outcomes(&summary),
+        );
+
+        assert_eq!(page.matches("<svg").count(), 4);
+        assert!(page.contains("<td>200</td>"));
+        assert!(!page.contains("<script"));
+        assert!(!page.contains("https://"));
+    }
+
+    #[test]
+    fn test_request_template_flags() {
+        let args = parse_args([
+            "cli_load_test",
+            "-t",
+            "http://localhost:8080/person",
+            "-X",
+            "POST",
+            "-H",
+            "Content-Type: application/json",
+            "-H",
+            "X-Trace: 1",
+            "--body",
+            "{\"name\":\"John\"}",
+        ])
+        .unwrap();
+
+        let endpoints = build_endpoints(&args).unwrap();
+        let request = &endpoints[0].request;
+        assert_eq!(endpoints[0].name, "POST /person");
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.headers.len(), 2);
+        assert_eq!(request.body, "{\"name\":\"John\"}".parse().unwrap());
+    }
 }
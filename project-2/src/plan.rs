@@ -0,0 +1,447 @@
+// A load test described in a file, so that it can be kept under version control
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use benchmark::{Stage, StatusSet};
+use clap::{parser::ValueSource, ArgMatches};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    check::parse_check, Args, EndpointArg, FeederArg, OutputFormat, StageTargetArg, StepArg,
+    Threshold, CONNECTION_RANGE,
+};
+
+#[derive(clap::Args, Debug)]
+pub(crate) struct RunArgs {
+    /// Plan file, TOML or YAML depending on its extension
+    pub(crate) plan: PathBuf,
+
+    #[command(flatten)]
+    pub(crate) args: Args,
+}
+
+/// Everything a run can be configured with, under the same names as the flags.
+/// Every value is optional and flags given on the command line take precedence,
+/// switches included: `--http2=false` turns off `http2 = true` of the plan.
+/// Files the plan reads, TLS certificates, feeders and a body given as "@path", are
+/// relative to the plan; the files it writes are relative to the working directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Plan {
+    target: Option<String>,
+    request: RequestPlan,
+    endpoints: Vec<EndpointArg>,
+    steps: Vec<StepArg>,
+    feeders: Vec<FeederArg>,
+    load: LoadPlan,
+    timeouts: TimeoutsPlan,
+    tls: TlsPlan,
+    output: OutputPlan,
+    thresholds: Vec<Parsed<Threshold>>,
+    // "[name=]condition" like --check, run on every response
+    checks: Vec<String>,
+    success_status: Option<Parsed<StatusSet>>,
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RequestPlan {
+    method: Option<String>,
+    headers: BTreeMap<String, String>,
+    body: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LoadPlan {
+    connections: Option<u16>,
+    requests: Option<u64>,
+    duration: Option<Parsed<humantime::Duration>>,
+    rate: Option<u32>,
+    stages: Vec<Parsed<Stage>>,
+    stage_target: Option<StageTargetArg>,
+    http2: Option<bool>,
+    streams: Option<u16>,
+    latency_precision: Option<u8>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TimeoutsPlan {
+    request: Option<Parsed<humantime::Duration>>,
+    connect: Option<Parsed<humantime::Duration>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TlsPlan {
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    insecure: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct OutputPlan {
+    file: Option<String>,
+    format: Option<OutputFormat>,
+    html: Option<PathBuf>,
+    timeseries: Option<PathBuf>,
+    raw_log: Option<PathBuf>,
+    interval: Option<Parsed<humantime::Duration>>,
+    live: Option<bool>,
+}
+
+// A value written as a string, parsed the same way as the flag it stands for
+#[derive(Debug)]
+struct Parsed<T>(T);
+
+impl<'de, T> Deserialize<'de> for Parsed<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map(Parsed).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Plan {
+    pub(crate) fn load(path: &Path) -> Result<Plan, Box<dyn Error>> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read plan {}: {}", path.display(), e))?;
+        let invalid = |e: &dyn Display| format!("invalid plan {}: {}", path.display(), e);
+        let mut plan: Plan = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => toml::from_str(&content).map_err(|e| invalid(&e))?,
+            Some("yaml" | "yml") => serde_yaml::from_str(&content).map_err(|e| invalid(&e))?,
+            _ => return Err(format!("plan {} is neither .toml nor .yaml", path.display()).into()),
+        };
+        plan.dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(plan)
+    }
+
+    /// Fills in what was not given on the command line, then checks the result as
+    /// a whole since the values of the plan did not go through the flag parser.
+    pub(crate) fn apply(
+        self,
+        mut args: Args,
+        matches: &ArgMatches,
+    ) -> Result<Args, Box<dyn Error>> {
+        let cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let resolve = |path: PathBuf| self.dir.join(path);
+        macro_rules! fill {
+            ($field:ident, $value:expr) => {
+                if !cli(stringify!($field)) {
+                    if let Some(value) = $value {
+                        args.$field = value;
+                    }
+                }
+            };
+        }
+        let duration = |d: Parsed<humantime::Duration>| d.0.into();
+        let body = |body: String| match body.strip_prefix('@') {
+            Some(path) => format!("@{}", resolve(path.into()).display()),
+            None => body,
+        };
+
+        fill!(target_uri, self.target.map(Some));
+        fill!(method, self.request.method);
+        fill!(body, self.request.body.map(|b| Some(body(b))));
+        let endpoints: Vec<EndpointArg> = self
+            .endpoints
+            .into_iter()
+            .map(|endpoint| EndpointArg {
+                body: endpoint.body.map(body),
+                ..endpoint
+            })
+            .collect();
+        fill!(endpoints, (!endpoints.is_empty()).then_some(endpoints));
+        // Steps have no flag, only a plan sets them
+        args.steps = self
+            .steps
+            .into_iter()
+            .map(|step| StepArg {
+                body: step.body.map(body),
+                ..step
+            })
+            .collect();
+        let feeders: Vec<FeederArg> = self
+            .feeders
+            .into_iter()
+            .map(|feeder| FeederArg {
+                file: resolve(feeder.file),
+                ..feeder
+            })
+            .collect();
+        fill!(feeders, (!feeders.is_empty()).then_some(feeders));
+        // Headers of the command line replace those of the plan with the same name
+        let names: Vec<String> = args
+            .headers
+            .iter()
+            .filter_map(|h| h.split_once(':'))
+            .map(|(name, _)| name.trim().to_ascii_lowercase())
+            .collect();
+        let mut headers: Vec<String> = self
+            .request
+            .headers
+            .into_iter()
+            .filter(|(name, _)| !names.contains(&name.to_ascii_lowercase()))
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        headers.append(&mut args.headers);
+        args.headers = headers;
+
+        let load = self.load;
+        fill!(connections, load.connections);
+        // Requests, a duration and stages are different ways to bound the run,
+        // choosing one on the command line drops the choice of the plan
+        if !["requests", "duration", "stages"].into_iter().any(cli) {
+            // The requests flag has a default, so the check can't wait for validate
+            if load.requests.is_some() && load.duration.is_some() {
+                return Err(
+                    "load.requests and load.duration both bound the run, keep one of them".into(),
+                );
+            }
+            fill!(requests, load.requests);
+            fill!(duration, load.duration.map(|d| Some(duration(d))));
+            let stages: Vec<Stage> = load.stages.into_iter().map(|s| s.0).collect();
+            fill!(stages, (!stages.is_empty()).then_some(stages));
+        }
+        fill!(rate, load.rate.map(Some));
+        fill!(stage_target, load.stage_target);
+        fill!(streams, load.streams);
+        fill!(latency_precision, load.latency_precision);
+        fill!(http2, load.http2);
+
+        fill!(timeout, self.timeouts.request.map(duration));
+        fill!(connect_timeout, self.timeouts.connect.map(duration));
+
+        fill!(ca_cert, self.tls.ca_cert.map(|p| Some(resolve(p))));
+        fill!(client_cert, self.tls.client_cert.map(|p| Some(resolve(p))));
+        fill!(client_key, self.tls.client_key.map(|p| Some(resolve(p))));
+        fill!(insecure, self.tls.insecure);
+
+        let output = self.output;
+        fill!(output_file, output.file.map(Some));
+        fill!(format, output.format.map(Some));
+        fill!(html, output.html.map(Some));
+        fill!(timeseries, output.timeseries.map(Some));
+        fill!(raw_log, output.raw_log.map(Some));
+        fill!(interval, output.interval.map(duration));
+        fill!(live, output.live);
+
+        let thresholds: Vec<Threshold> = self.thresholds.into_iter().map(|t| t.0).collect();
+        fill!(thresholds, (!thresholds.is_empty()).then_some(thresholds));
+        let checks = self
+            .checks
+            .iter()
+            .map(|check| parse_check(check))
+            .collect::<Result<Vec<_>, _>>()?;
+        fill!(checks, (!checks.is_empty()).then_some(checks));
+        fill!(success_statuses, self.success_status.map(|s| s.0));
+
+        validate(&args)?;
+        Ok(args)
+    }
+}
+
+// The checks the flag parser does on its own, for the values that came from a plan
+fn validate(args: &Args) -> Result<(), String> {
+    if args.target_uri.is_none() {
+        return Err(String::from(
+            "no target, set `target` in the plan or pass --target-uri",
+        ));
+    }
+    if !CONNECTION_RANGE.contains(&(args.connections as usize)) {
+        return Err(format!(
+            "load.connections must be in range {}-{}",
+            CONNECTION_RANGE.start(),
+            CONNECTION_RANGE.end()
+        ));
+    }
+    if !args.endpoints.is_empty() && !args.steps.is_empty() {
+        return Err(String::from(
+            "endpoints and steps are two kinds of load, keep one of them",
+        ));
+    }
+    if args.duration.is_some() && !args.stages.is_empty() {
+        return Err(String::from(
+            "load.duration and load.stages both bound the run, keep one of them",
+        ));
+    }
+    if args.rate.is_some() && !args.stages.is_empty() {
+        return Err(String::from(
+            "load.rate does not apply to stages, set load.stage_target = \"rate\" instead",
+        ));
+    }
+    if args.rate == Some(0) {
+        return Err(String::from("load.rate must be at least 1"));
+    }
+    if args.streams == 0 || (args.streams > 1 && !args.http2) {
+        return Err(String::from(
+            "load.streams must be at least 1, and more than 1 needs load.http2",
+        ));
+    }
+    if !(1..=5).contains(&args.latency_precision) {
+        return Err(String::from(
+            "load.latency_precision must be between 1 and 5",
+        ));
+    }
+    if args.interval.is_zero() {
+        return Err(String::from("output.interval must be longer than zero"));
+    }
+    if args.client_cert.is_some() != args.client_key.is_some() {
+        return Err(String::from(
+            "tls.client_cert and tls.client_key must be given together",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+    use crate::{feeder::FeedScopeArg, Cli};
+
+    const PLAN: &str = r#"
+target = "http://localhost:8080/person"
+thresholds = ["p99<250ms", "error_rate<1%"]
+checks = ["status:2xx"]
+success_status = "2xx,304"
+
+[request]
+method = "POST"
+body = "@payload.json"
+headers = { "Content-Type" = "application/json", "X-Trace" = "plan" }
+
+[[endpoints]]
+name = "read"
+weight = 9
+method = "GET"
+uri = "/person"
+
+[[endpoints]]
+name = "write"
+uri = "/person"
+body = "@person.json"
+
+[[feeders]]
+file = "users.csv"
+per = "user"
+
+[load]
+connections = 64
+stages = ["30s:0->500", "5m:500"]
+http2 = true
+
+[timeouts]
+request = "2s"
+
+[tls]
+insecure = true
+
+[output]
+file = "result.csv"
+live = true
+"#;
+
+    fn apply(plan: Plan, flags: &[&str]) -> Result<Args, Box<dyn Error>> {
+        let matches = Cli::command()
+            .try_get_matches_from(["cli_load_test", "run", "plan.toml"].iter().chain(flags))
+            .unwrap();
+        let matches = matches.subcommand_matches("run").unwrap();
+        let run = RunArgs::from_arg_matches(matches).unwrap();
+        plan.apply(run.args, matches)
+    }
+
+    fn plan(toml: &str) -> Plan {
+        Plan {
+            dir: PathBuf::from("plans"),
+            ..toml::from_str(toml).unwrap()
+        }
+    }
+
+    #[test]
+    fn flags_override_the_plan() {
+        let args = apply(plan(PLAN), &[]).unwrap();
+        assert_eq!(args.target_uri(), "http://localhost:8080/person");
+        assert_eq!(args.method, "POST");
+        assert_eq!(args.body.as_deref(), Some("@plans/payload.json"));
+        assert_eq!(args.connections, 64);
+        assert_eq!(args.stages.len(), 2);
+        assert_eq!(args.timeout, Duration::from_secs(2));
+        assert_eq!(args.connect_timeout, Duration::from_secs(10));
+        assert_eq!(args.thresholds.len(), 2);
+        assert_eq!(args.checks[0].name, "status:2xx");
+        assert_eq!(args.success_statuses.to_string(), "2xx,304");
+        assert_eq!(args.output_file.as_deref(), Some("result.csv"));
+        assert_eq!(args.endpoints.len(), 2);
+        assert_eq!(args.endpoints[0].weight, 9);
+        assert_eq!(args.endpoints[1].weight, 1);
+        assert_eq!(
+            args.endpoints[1].body.as_deref(),
+            Some("@plans/person.json")
+        );
+        assert_eq!(args.feeders[0].file, PathBuf::from("plans/users.csv"));
+        assert_eq!(args.feeders[0].per, FeedScopeArg::User);
+        assert!(args.http2 && args.insecure && args.live);
+
+        let args = apply(plan(PLAN), &["-c", "8", "-d", "10s", "-H", "x-trace: cli"]).unwrap();
+        assert_eq!(args.connections, 8);
+        assert_eq!(args.duration, Some(Duration::from_secs(10)));
+        assert!(args.stages.is_empty());
+        assert_eq!(
+            args.headers,
+            vec!["Content-Type: application/json", "x-trace: cli"]
+        );
+
+        let args = apply(plan(PLAN), &["--http2=false", "-k=false", "--live=false"]).unwrap();
+        assert!(!args.http2 && !args.insecure && !args.live);
+    }
+
+    #[test]
+    fn reports_invalid_plans() {
+        let unknown = toml::from_str::<Plan>("[load]\nconections = 10").unwrap_err();
+        assert!(unknown.to_string().contains("unknown field `conections`"));
+        let stage = toml::from_str::<Plan>("[load]\nstages = [\"30s\"]").unwrap_err();
+        assert!(stage.to_string().contains("not in the form"));
+
+        let yaml: Plan = serde_yaml::from_str("load:\n  rate: 100\n  stages: [\"1m:10\"]").unwrap();
+        let conflict = apply(yaml, &["-t", "http://localhost"]).unwrap_err();
+        assert!(conflict.to_string().contains("load.rate"));
+
+        let steps: Plan = toml::from_str("[[steps]]\nname = \"a\"\nuri = \"/\"").unwrap();
+        let conflict =
+            apply(steps, &["-t", "http://localhost", "--endpoint", "b=1:/"]).unwrap_err();
+        assert!(conflict.to_string().contains("steps"));
+
+        let bounds: Plan = toml::from_str("[load]\nrequests = 100\nduration = \"30s\"").unwrap();
+        let conflict = apply(bounds, &["-t", "http://localhost"]).unwrap_err();
+        assert!(conflict
+            .to_string()
+            .contains("load.requests and load.duration"));
+        let bounds: Plan = toml::from_str("[load]\nrequests = 100\nduration = \"30s\"").unwrap();
+        let args = apply(bounds, &["-t", "http://localhost", "-r", "50"]).unwrap();
+        assert_eq!((args.requests, args.duration), (50, None));
+
+        let interval: Plan = toml::from_str("[output]\ninterval = \"0s\"").unwrap();
+        let zero = apply(interval, &["-t", "http://localhost"]).unwrap_err();
+        assert!(zero.to_string().contains("output.interval"));
+
+        let missing = apply(Plan::default(), &[]).unwrap_err();
+        assert!(missing.to_string().contains("no target"));
+    }
+}
@@ -0,0 +1,134 @@
+// User journeys: requests sent in order, passing values from one to the next
+use std::{collections::BTreeMap, error::Error};
+
+use benchmark::{Scenario, Step};
+use serde::Deserialize;
+
+use crate::{build_request, check::with_own_checks, endpoint::resolve_uri, Args};
+
+/// One step of a scenario, only given in a plan as `[[steps]]`. The URI, headers
+/// and body may use `{{name}}` for a value of a feeder or extracted by an earlier step.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StepArg {
+    pub(crate) name: String,
+    // GET when not set, steps do not follow --method
+    pub(crate) method: Option<String>,
+    // A URI starting with / is a path on the target
+    pub(crate) uri: String,
+    // Sent along with the headers of --header
+    #[serde(default)]
+    pub(crate) headers: BTreeMap<String, String>,
+    // "@path" reads it from a file
+    pub(crate) body: Option<String>,
+    // Variable name to "json:$.path", "header:Name" or "regex:pattern"
+    #[serde(default)]
+    pub(crate) extract: BTreeMap<String, String>,
+    // Check name to condition, run along with those of --check
+    #[serde(default)]
+    pub(crate) checks: BTreeMap<String, String>,
+}
+
+/// The scenario of the steps, `None` without steps.
+pub(crate) fn build_scenario(args: &Args) -> Result<Option<Scenario>, Box<dyn Error>> {
+    if args.steps.is_empty() {
+        return Ok(None);
+    }
+    let mut names = vec![];
+    let mut steps = vec![];
+    for arg in &args.steps {
+        if names.contains(&&arg.name) {
+            return Err(format!("Step name {:?} is used twice", arg.name).into());
+        }
+        names.push(&arg.name);
+        let headers: Vec<String> = args
+            .headers
+            .iter()
+            .cloned()
+            .chain(
+                arg.headers
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value)),
+            )
+            .collect();
+        let request = build_request(
+            &resolve_uri(args.target_uri(), &arg.uri),
+            arg.method.as_deref().unwrap_or("GET"),
+            &headers,
+            arg.body.as_deref(),
+        )?;
+        let mut step = Step::new(&arg.name, request);
+        step.checks = with_own_checks(&args.checks, &arg.checks, &format!("Step {:?}", arg.name))?;
+        for (variable, extractor) in &arg.extract {
+            let extractor = extractor
+                .parse()
+                .map_err(|e| format!("Step {:?} extracts {}: {}", arg.name, variable, e))?;
+            step = step.with_extraction(variable, extractor);
+        }
+        steps.push(step);
+    }
+    Ok(Some(Scenario::new(steps)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::Cli;
+
+    fn step(name: &str, uri: &str, extract: &[(&str, &str)]) -> StepArg {
+        StepArg {
+            name: name.to_string(),
+            method: None,
+            uri: uri.to_string(),
+            headers: BTreeMap::from([(String::from("Authorization"), String::from("{{token}}"))]),
+            body: None,
+            extract: extract
+                .iter()
+                .map(|(variable, extractor)| (variable.to_string(), extractor.to_string()))
+                .collect(),
+            checks: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn builds_steps_on_the_target() {
+        let mut args = Cli::try_parse_from([
+            "cli_load_test",
+            "-t",
+            "http://localhost:8080/",
+            "-H",
+            "X-Run: 1",
+            "--check",
+            "status:2xx",
+        ])
+        .unwrap()
+        .args;
+        args.steps = vec![
+            StepArg {
+                headers: BTreeMap::new(),
+                ..step("login", "/login", &[("token", "json:$.token")])
+            },
+            StepArg {
+                checks: BTreeMap::from([(String::from("named"), String::from("body:Ada"))]),
+                ..step("person", "http://other/person", &[])
+            },
+        ];
+        let scenario = build_scenario(&args).unwrap().unwrap();
+        let steps = scenario.steps();
+        assert_eq!(
+            steps[0].request.uri,
+            "http://localhost:8080/login".parse().unwrap()
+        );
+        assert_eq!(steps[1].request.uri, "http://other/person".parse().unwrap());
+        assert_eq!(steps[1].request.headers.len(), 2);
+        assert_eq!(steps[0].checks.len(), 1);
+        assert_eq!(steps[1].checks[1].name, "named");
+
+        args.steps[0]
+            .extract
+            .insert(String::from("token"), String::from("xml:token"));
+        assert!(build_scenario(&args).is_err());
+    }
+}
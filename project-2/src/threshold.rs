@@ -0,0 +1,291 @@
+// Pass/fail criteria checked against the statistics once the run is done
+use std::{fmt, str::FromStr};
+
+use crate::{format_float, StatusStatistics, Totals};
+
+/// A limit on one metric, e.g. `p99<250ms`, `error_rate<1%` or `rps>1000`.
+/// A status prefix checks the requests of that status only, e.g. `200:p99<100ms`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Threshold {
+    metric: Metric,
+    comparison: Comparison,
+    limit: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Metric {
+    // A statistic of the requests of one status, or of all requests without one
+    Statistic {
+        status: Option<String>,
+        statistic: Statistic,
+    },
+    // Only defined over all requests
+    ErrorRate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Statistic {
+    Min,
+    Max,
+    Mean,
+    P90,
+    P99,
+    P90Corrected,
+    P99Corrected,
+    Requests,
+    Rps,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// The outcome of checking one threshold.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Verdict {
+    pub(crate) passed: bool,
+    // Not known when no request got the status of the threshold,
+    // or for the rate when no time passed
+    pub(crate) actual: Option<f64>,
+}
+
+impl Metric {
+    // A status is only taken by the statistics
+    fn parse(status: Option<String>, name: &str) -> Result<Self, String> {
+        match (status, name) {
+            (None, "error_rate") => Ok(Metric::ErrorRate),
+            (Some(_), "error_rate") => Err(String::from(
+                "error_rate is over all requests, it takes no status",
+            )),
+            (status, name) => Ok(Metric::Statistic {
+                status,
+                statistic: Statistic::parse(name)?,
+            }),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Metric::Statistic { statistic, .. } => statistic.name(),
+            Metric::ErrorRate => "error_rate",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Metric::Statistic {
+                statistic: Statistic::Requests | Statistic::Rps,
+                ..
+            } => "",
+            Metric::Statistic { .. } => "ms",
+            Metric::ErrorRate => "%",
+        }
+    }
+
+    // Latencies accept a duration like "250ms" or "1s", or plain milliseconds
+    fn parse_limit(&self, limit: &str) -> Result<f64, String> {
+        let invalid = || format!("Invalid limit {:?} for {}", limit, self.name());
+        let number = match self.unit() {
+            "ms" => match humantime::parse_duration(limit) {
+                Ok(duration) => return Ok(duration.as_micros() as f64 / 1000_f64),
+                Err(_) => limit,
+            },
+            "%" => limit.strip_suffix('%').unwrap_or(limit),
+            _ => limit,
+        };
+        number.trim().parse().map_err(|_| invalid())
+    }
+}
+
+impl Statistic {
+    fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "min" => Statistic::Min,
+            "max" => Statistic::Max,
+            "mean" => Statistic::Mean,
+            "p90" => Statistic::P90,
+            "p99" => Statistic::P99,
+            "p90_corrected" => Statistic::P90Corrected,
+            "p99_corrected" => Statistic::P99Corrected,
+            "requests" => Statistic::Requests,
+            "rps" => Statistic::Rps,
+            _ => return Err(format!("Unknown threshold metric {:?}", name)),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Statistic::Min => "min",
+            Statistic::Max => "max",
+            Statistic::Mean => "mean",
+            Statistic::P90 => "p90",
+            Statistic::P99 => "p99",
+            Statistic::P90Corrected => "p90_corrected",
+            Statistic::P99Corrected => "p99_corrected",
+            Statistic::Requests => "requests",
+            Statistic::Rps => "rps",
+        }
+    }
+
+    fn value(&self, statistics: &StatusStatistics) -> Option<f64> {
+        Some(match self {
+            Statistic::Min => statistics.min,
+            Statistic::Max => statistics.max,
+            Statistic::Mean => statistics.mean,
+            Statistic::P90 => statistics.p90,
+            Statistic::P99 => statistics.p99,
+            Statistic::P90Corrected => statistics.p90_corrected,
+            Statistic::P99Corrected => statistics.p99_corrected,
+            Statistic::Requests => statistics.requests as f64,
+            Statistic::Rps => return statistics.average_rate,
+        })
+    }
+}
+
+impl Comparison {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    fn holds(&self, actual: f64, limit: f64) -> bool {
+        match self {
+            Comparison::Less => actual < limit,
+            Comparison::LessOrEqual => actual <= limit,
+            Comparison::Greater => actual > limit,
+            Comparison::GreaterOrEqual => actual >= limit,
+        }
+    }
+}
+
+impl Threshold {
+    pub(crate) fn check(&self, statuses: &[StatusStatistics], totals: &Totals) -> Verdict {
+        let actual = match &self.metric {
+            Metric::ErrorRate => Some(totals.error_rate),
+            Metric::Statistic {
+                status: None,
+                statistic,
+            } => statistic.value(&totals.statistics),
+            Metric::Statistic {
+                status: Some(status),
+                statistic,
+            } => match statuses.iter().find(|s| &s.status == status) {
+                Some(statistics) => statistic.value(statistics),
+                // No request got the status, there is nothing to count or measure
+                None if matches!(statistic, Statistic::Requests | Statistic::Rps) => Some(0_f64),
+                None => None,
+            },
+        };
+        Verdict {
+            passed: actual.is_some_and(|actual| self.comparison.holds(actual, self.limit)),
+            actual,
+        }
+    }
+
+    pub(crate) fn format_verdict(&self, verdict: &Verdict) -> String {
+        let actual = match verdict.actual {
+            Some(actual) => format!("{}{}", format_float(&actual), self.metric.unit()),
+            None => String::from("not measured"),
+        };
+        let result = if verdict.passed { "pass" } else { "FAIL" };
+        format!("threshold {}: {} (actual {})", self, result, actual)
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let at = s
+            .find(['<', '>'])
+            .ok_or_else(|| format!("Threshold {:?} is not in the form 'metric<limit'", s))?;
+        let (left, right) = s.split_at(at);
+        let (comparison, limit) = match right.split_at(1) {
+            ("<", rest) => match rest.strip_prefix('=') {
+                Some(limit) => (Comparison::LessOrEqual, limit),
+                None => (Comparison::Less, rest),
+            },
+            (_, rest) => match rest.strip_prefix('=') {
+                Some(limit) => (Comparison::GreaterOrEqual, limit),
+                None => (Comparison::Greater, rest),
+            },
+        };
+        let (status, metric) = match left.split_once(':') {
+            Some((status, metric)) => (Some(status.trim().to_string()), metric),
+            None => (None, left),
+        };
+        let metric = Metric::parse(status, metric.trim())?;
+        Ok(Threshold {
+            limit: metric.parse_limit(limit.trim())?,
+            metric,
+            comparison,
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Metric::Statistic {
+            status: Some(status),
+            ..
+        } = &self.metric
+        {
+            write!(f, "{}:", status)?;
+        }
+        write!(
+            f,
+            "{}{}{}{}",
+            self.metric.name(),
+            self.comparison.symbol(),
+            self.limit,
+            self.metric.unit()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_limits_in_the_unit_of_the_metric() {
+        let p99: Threshold = "p99<250ms".parse().unwrap();
+        assert_eq!(
+            p99.metric,
+            Metric::Statistic {
+                status: None,
+                statistic: Statistic::P99
+            }
+        );
+        assert_eq!(p99.comparison, Comparison::Less);
+        assert_eq!(p99.limit, 250_f64);
+        assert_eq!("p90 <= 1s".parse::<Threshold>().unwrap().limit, 1000_f64);
+
+        let errors: Threshold = "error_rate<1%".parse().unwrap();
+        assert_eq!(errors.limit, 1_f64);
+        assert_eq!(errors.to_string(), "error_rate<1%");
+
+        let rps: Threshold = "200:rps>=1000".parse().unwrap();
+        assert_eq!(
+            rps.metric,
+            Metric::Statistic {
+                status: Some(String::from("200")),
+                statistic: Statistic::Rps
+            }
+        );
+        assert_eq!(rps.comparison, Comparison::GreaterOrEqual);
+
+        assert!("p99".parse::<Threshold>().is_err());
+        assert!("p42<1ms".parse::<Threshold>().is_err());
+        assert!("rps>lots".parse::<Threshold>().is_err());
+        assert!("500:error_rate<1%".parse::<Threshold>().is_err());
+    }
+}